@@ -0,0 +1,1112 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::int::error::{TypeError, error_struct};
+use crate::int::lexeme::*;
+use crate::int::native::{build_native_const_table, build_native_fn_table};
+
+// Static type of an expression. `Any` is used wherever the checker can't know
+// (unannotated parameters, natives), and is compatible with everything.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Type {
+    Int,
+    Float,
+    Str,
+    Bool,
+    Function(Option<Rc<Signature>>), // None when the signature isn't known
+    List,
+    Map,
+    Struct(String), // by name
+    Enum(String),   // by name
+    Vec2,
+    Unit,
+    Optional, // the wrapped type isn't tracked
+    Any,
+}
+
+impl Type {
+    // Resolve a `var name: type` annotation.
+    pub fn from_annotation(name: &str) -> Option<Type> {
+        match name {
+            "int" => Some(Type::Int),
+            "float" => Some(Type::Float),
+            "str" => Some(Type::Str),
+            "bool" => Some(Type::Bool),
+            "fn" => Some(Type::Function(None)),
+            "list" => Some(Type::List),
+            "map" => Some(Type::Map),
+            "vec2" => Some(Type::Vec2),
+            "optional" => Some(Type::Optional),
+            "any" => Some(Type::Any),
+            _ => None,
+        }
+    }
+
+    pub fn of_value(value: &Value) -> Type {
+        match value {
+            Value::Int(_) => Type::Int,
+            Value::Float(_) => Type::Float,
+            Value::Str(_) => Type::Str,
+            Value::Bool(_) => Type::Bool,
+            Value::Native(_) | Value::Function(_) => Type::Function(None),
+            Value::List(_) => Type::List,
+            Value::Map(_) => Type::Map,
+            Value::Struct(instance) => Type::Struct(instance.decl.name.clone()),
+            Value::Enum(value) => Type::Enum(value.decl.name.clone()),
+            Value::Vec2(_) => Type::Vec2,
+            Value::Unit => Type::Unit,
+            Value::Optional(_) => Type::Optional,
+        }
+    }
+
+    // Whether a value of type `other` can be stored where `self` is expected.
+    // An int goes where a float is expected, like it does in arithmetic.
+    pub fn accepts(&self, other: &Type) -> bool {
+        match (self, other) {
+            (Type::Any, _) | (_, Type::Any) => true,
+            (Type::Float, Type::Int) => true,
+            (Type::Function(a), Type::Function(b)) => match (a, b) {
+                (Some(a), Some(b)) => a == b,
+                _ => true,
+            },
+            _ => self == other,
+        }
+    }
+
+    fn is_numeric(&self) -> bool {
+        matches!(self, Type::Int | Type::Float | Type::Any)
+    }
+}
+
+impl std::fmt::Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            Type::Int => "int",
+            Type::Float => "float",
+            Type::Str => "str",
+            Type::Bool => "bool",
+            Type::Function(None) => "fn",
+            Type::List => "list",
+            Type::Map => "map",
+            Type::Vec2 => "vec2",
+            Type::Unit => "nil",
+            Type::Optional => "optional",
+            Type::Struct(name) | Type::Enum(name) => name,
+            Type::Function(Some(sig)) => {
+                let params: Vec<String> = sig.parameters.iter().map(|t| t.to_string()).collect();
+                return write!(f, "fn({}) -> {}", params.join(", "), sig.return_type);
+            }
+            Type::Any => "any",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// Parameter and return types of a user function.
+#[derive(Debug, PartialEq)]
+pub struct Signature {
+    parameters: Vec<Type>,
+    return_type: Type,
+}
+
+// Check a parsed program before it runs, collecting every type error.
+pub fn check_program(stmts: &[Statement]) -> Vec<TypeError> {
+    let mut checker = Checker::new();
+    checker.check_block(stmts);
+    checker.errors
+}
+
+// A name in scope: its type, and whether it was declared with `const`.
+struct Binding {
+    typ: Type,
+    constant: bool,
+}
+
+struct Checker {
+    // Variables, innermost scope last, mirroring the interpreter.
+    scopes: Vec<HashMap<String, Binding>>,
+    // Field types of each struct declared so far.
+    structs: HashMap<String, Vec<(String, Type)>>,
+    // Variants of each enum declared so far, with their payload types.
+    enums: HashMap<String, Vec<(String, Vec<Type>)>>,
+    // Declared return type of each function being checked, innermost last.
+    returns: Vec<Type>,
+    errors: Vec<TypeError>,
+}
+
+impl Checker {
+    fn new() -> Self {
+        let mut globals = HashMap::new();
+        for name in build_native_fn_table().keys() {
+            let typ = Type::Function(None);
+            globals.insert(
+                name.clone(),
+                Binding {
+                    typ,
+                    constant: false,
+                },
+            );
+        }
+        for (name, value) in build_native_const_table() {
+            let typ = Type::of_value(&value);
+            globals.insert(
+                name,
+                Binding {
+                    typ,
+                    constant: true,
+                },
+            );
+        }
+        // The struct `catch` blocks receive.
+        let error = error_struct();
+        let error_fields = error
+            .fields
+            .iter()
+            .map(|f| {
+                let typ = Type::from_annotation(&f.type_annotation).unwrap_or(Type::Any);
+                (f.name.clone(), typ)
+            })
+            .collect();
+        Checker {
+            scopes: vec![globals],
+            structs: HashMap::from([(error.name.clone(), error_fields)]),
+            enums: HashMap::new(),
+            returns: Vec::new(),
+            errors: Vec::new(),
+        }
+    }
+
+    fn error(&mut self, message: String, span: Span) {
+        self.errors.push(TypeError { message, span });
+    }
+
+    fn declare(&mut self, name: &str, typ: Type) {
+        self.declare_binding(name, typ, false, false);
+    }
+
+    fn declare_binding(&mut self, name: &str, typ: Type, constant: bool, global: bool) {
+        let scope = if global {
+            self.scopes.first_mut()
+        } else {
+            self.scopes.last_mut()
+        };
+        if let Some(scope) = scope {
+            scope.insert(name.to_string(), Binding { typ, constant });
+        }
+    }
+
+    fn binding(&self, name: &str) -> Option<&Binding> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name))
+    }
+
+    fn lookup(&self, name: &str) -> Option<Type> {
+        self.binding(name).map(|b| b.typ.clone())
+    }
+
+    // Resolve an annotation, reporting unknown type names.
+    fn resolve(&mut self, annotation: &str, span: Span) -> Type {
+        if let Some(typ) = Type::from_annotation(annotation) {
+            return typ;
+        }
+        if self.structs.contains_key(annotation) {
+            return Type::Struct(annotation.to_string());
+        }
+        if self.enums.contains_key(annotation) {
+            return Type::Enum(annotation.to_string());
+        }
+        self.error(format!("Unknown type '{}'", annotation), span);
+        Type::Any
+    }
+
+    fn expect(&mut self, expected: &Type, found: &Type, what: &str, span: Span) {
+        if !expected.accepts(found) {
+            self.error(
+                format!("{} should be {}, found {}", what, expected, found),
+                span,
+            );
+        }
+    }
+
+    fn check_scoped_block(&mut self, stmts: &[Statement]) {
+        self.scopes.push(HashMap::new());
+        self.check_block(stmts);
+        self.scopes.pop();
+    }
+
+    fn check_block(&mut self, stmts: &[Statement]) {
+        for stmt in stmts {
+            self.check_statement(stmt);
+        }
+    }
+
+    fn check_statement(&mut self, stmt: &Statement) {
+        match stmt {
+            Statement::VarDecl(var) => {
+                let declared = self.resolve(&var.type_annotation, var.span);
+                if let Some(init) = &var.initializer {
+                    let found = self.infer(init);
+                    let what = format!("Initial value of '{}'", var.name);
+                    self.expect(&declared, &found, &what, init.span());
+                } else if var.kind == VarKind::Const {
+                    self.error(format!("Constant '{}' needs a value", var.name), var.span);
+                }
+                let constant = var.kind == VarKind::Const;
+                let global = var.kind == VarKind::Global;
+                // Redeclaring in the same scope, or through `global`, would replace the constant.
+                let scope = if global {
+                    self.scopes.first()
+                } else {
+                    self.scopes.last()
+                };
+                if scope
+                    .and_then(|scope| scope.get(&var.name))
+                    .is_some_and(|b| b.constant)
+                {
+                    self.error(
+                        format!("Cannot assign to constant '{}'", var.name),
+                        var.span,
+                    );
+                }
+                self.declare_binding(&var.name, declared, constant, global);
+            }
+            Statement::PrintExpr(expr) => {
+                self.infer(expr);
+            }
+            Statement::ForLoop {
+                var_name,
+                iterable,
+                body,
+                ..
+            } => {
+                let item = self.check_iterable(iterable);
+                self.scopes.push(HashMap::new());
+                self.declare(var_name, item);
+                self.check_block(body);
+                self.scopes.pop();
+            }
+            Statement::While {
+                condition, body, ..
+            } => {
+                self.check_condition(condition);
+                self.check_scoped_block(body);
+            }
+            Statement::If {
+                condition,
+                then_body,
+                else_body,
+            } => {
+                self.check_condition(condition);
+                self.check_scoped_block(then_body);
+                if let Some(else_body) = else_body {
+                    self.check_scoped_block(else_body);
+                }
+            }
+            Statement::Function(decl) => {
+                // Declared before the body so recursive calls are checked too.
+                let sig = self.signature(decl);
+                self.declare(decl.display_name(), Type::Function(Some(sig.clone())));
+                self.check_function(decl, &sig);
+            }
+            Statement::Struct(decl) => {
+                // Registered first so fields can hold the struct itself.
+                self.structs.insert(decl.name.clone(), Vec::new());
+                let mut fields = Vec::new();
+                for field in &decl.fields {
+                    if fields.iter().any(|(name, _)| *name == field.name) {
+                        self.error(
+                            format!(
+                                "Field '{}' is declared twice in '{}'",
+                                field.name, decl.name
+                            ),
+                            decl.span,
+                        );
+                    }
+                    let typ = self.resolve(&field.type_annotation, decl.span);
+                    fields.push((field.name.clone(), typ));
+                }
+                self.structs.insert(decl.name.clone(), fields);
+            }
+            Statement::Enum(decl) => {
+                // Registered first so payloads can hold the enum itself.
+                self.enums.insert(decl.name.clone(), Vec::new());
+                let mut variants: Vec<(String, Vec<Type>)> = Vec::new();
+                for variant in &decl.variants {
+                    if variants.iter().any(|(name, _)| *name == variant.name) {
+                        self.error(
+                            format!(
+                                "Variant '{}' is declared twice in '{}'",
+                                variant.name, decl.name
+                            ),
+                            decl.span,
+                        );
+                    }
+                    let payload = variant
+                        .payload
+                        .iter()
+                        .map(|t| self.resolve(t, decl.span))
+                        .collect();
+                    variants.push((variant.name.clone(), payload));
+                }
+                self.enums.insert(decl.name.clone(), variants);
+            }
+            Statement::Match { subject, arms } => {
+                let subject_type = self.infer(subject);
+                for arm in arms {
+                    let mut bindings = Vec::new();
+                    self.check_pattern(&arm.pattern, &subject_type, arm.span, &mut bindings);
+                    self.scopes.push(HashMap::new());
+                    for (name, typ) in bindings {
+                        self.declare(&name, typ);
+                    }
+                    self.check_block(&arm.body);
+                    self.scopes.pop();
+                }
+                self.check_exhaustive(&subject_type, arms, subject.span());
+            }
+            Statement::Try {
+                body,
+                error_name,
+                catch_body,
+            } => {
+                self.check_scoped_block(body);
+                self.scopes.push(HashMap::new());
+                self.declare(error_name, Type::Struct("Error".to_string()));
+                self.check_block(catch_body);
+                self.scopes.pop();
+            }
+            // Any value can be thrown.
+            Statement::Throw(expr, _) => {
+                self.infer(expr);
+            }
+            Statement::Expression(expr) => {
+                self.infer(expr);
+            }
+            Statement::Assignment {
+                name, expr, span, ..
+            } => {
+                let found = self.infer(expr);
+                if self.binding(name).is_some_and(|b| b.constant) {
+                    self.error(format!("Cannot assign to constant '{}'", name), *span);
+                }
+                if let Some(declared) = self.lookup(name) {
+                    let what = format!("Value assigned to '{}'", name);
+                    self.expect(&declared, &found, &what, expr.span());
+                }
+            }
+            Statement::IndexAssignment {
+                target,
+                index,
+                expr,
+                span,
+            } => {
+                self.check_index(target, index, *span);
+                self.infer(expr);
+            }
+            Statement::FieldAssignment {
+                target,
+                field,
+                expr,
+                span,
+            } => {
+                let declared = self.check_field(target, field, *span);
+                let found = self.infer(expr);
+                let what = format!("Value assigned to '{}'", field);
+                self.expect(&declared, &found, &what, expr.span());
+            }
+            Statement::CompoundAssignment {
+                target,
+                op,
+                expr,
+                span,
+            } => {
+                let declared = self.check_target(target);
+                let found = self.infer(expr);
+                match binary_type(op, &declared, &found) {
+                    Some(result) => {
+                        let what = assigned_what(target);
+                        self.expect(&declared, &result, &what, *span);
+                    }
+                    None => self.error(
+                        format!("Cannot apply '{}=' to {} and {}", op, declared, found),
+                        *span,
+                    ),
+                }
+            }
+            Statement::MultiAssignment {
+                targets, values, ..
+            } => {
+                let found: Vec<Type> = values.iter().map(|value| self.infer(value)).collect();
+                for ((target, value), found) in targets.iter().zip(values).zip(found) {
+                    let declared = self.check_target(target);
+                    let what = assigned_what(target);
+                    self.expect(&declared, &found, &what, value.span());
+                }
+            }
+            Statement::Return(expr) => {
+                let found = self.infer(expr);
+                if let Some(expected) = self.returns.last().cloned() {
+                    self.expect(&expected, &found, "Returned value", expr.span());
+                }
+            }
+            Statement::Break(..) | Statement::Continue(..) => {}
+        }
+    }
+
+    // Check a for loop's range or collection and give the type of its items.
+    fn check_iterable(&mut self, iterable: &Iterable) -> Type {
+        match iterable {
+            Iterable::Range {
+                start, end, step, ..
+            } => {
+                for bound in [Some(start), Some(end), step.as_ref()]
+                    .into_iter()
+                    .flatten()
+                {
+                    let found = self.infer(bound);
+                    self.expect(&Type::Int, &found, "Range bound", bound.span());
+                }
+                Type::Int
+            }
+            Iterable::Collection(expr) => match self.infer(expr) {
+                Type::Str => Type::Str,
+                Type::List | Type::Map | Type::Any => Type::Any,
+                other => {
+                    self.error(format!("Cannot iterate over {}", other), expr.span());
+                    Type::Any
+                }
+            },
+        }
+    }
+
+    fn check_condition(&mut self, condition: &Expr) {
+        let found = self.infer(condition);
+        self.expect(&Type::Bool, &found, "Condition", condition.span());
+    }
+
+    // Resolve the annotations of a function declaration.
+    fn signature(&mut self, decl: &FunctionDecl) -> Rc<Signature> {
+        let parameters = decl
+            .parameters
+            .iter()
+            .map(|p| match &p.type_annotation {
+                Some(t) => self.resolve(t, decl.span),
+                None => Type::Any,
+            })
+            .collect();
+        let return_type = match &decl.return_type {
+            Some(t) => self.resolve(t, decl.span),
+            None => Type::Any,
+        };
+        Rc::new(Signature {
+            parameters,
+            return_type,
+        })
+    }
+
+    fn check_function(&mut self, decl: &FunctionDecl, sig: &Signature) {
+        self.scopes.push(HashMap::new());
+        for (param, typ) in decl.parameters.iter().zip(&sig.parameters) {
+            self.declare(&param.name, typ.clone());
+        }
+        self.returns.push(sig.return_type.clone());
+        self.check_block(&decl.body);
+        self.returns.pop();
+        self.scopes.pop();
+        if decl.return_type.is_some() && !always_returns(&decl.body) {
+            self.error(
+                format!(
+                    "Function '{}' does not return a value on every path",
+                    decl.display_name()
+                ),
+                decl.span,
+            );
+        }
+    }
+
+    // Check a call's arguments against the callee and give its result type.
+    fn check_call(&mut self, callee: &Expr, arguments: &[Expr], span: Span) -> Type {
+        let callee_type = self.infer(callee);
+        let found: Vec<Type> = arguments.iter().map(|a| self.infer(a)).collect();
+        let name = match callee {
+            Expr::Variable(name, _, _) => name.as_str(),
+            _ => "<lambda>",
+        };
+        let sig = match callee_type {
+            Type::Function(Some(sig)) => sig,
+            Type::Function(None) | Type::Any => return Type::Any,
+            other => {
+                self.error(format!("Cannot call a value of type {}", other), span);
+                return Type::Any;
+            }
+        };
+        if sig.parameters.len() != found.len() {
+            self.error(
+                format!(
+                    "Function '{}' expects {} argument(s), got {}",
+                    name,
+                    sig.parameters.len(),
+                    found.len()
+                ),
+                span,
+            );
+        }
+        for (i, (expected, (arg, typ))) in sig
+            .parameters
+            .iter()
+            .zip(arguments.iter().zip(&found))
+            .enumerate()
+        {
+            let what = format!("Argument {} of '{}'", i + 1, name);
+            self.expect(expected, typ, &what, arg.span());
+        }
+        sig.return_type.clone()
+    }
+
+    // Check `target[index]`. Items can be anything, so the result is unknown.
+    fn check_index(&mut self, target: &Expr, index: &Expr, span: Span) -> Type {
+        let target_type = self.infer(target);
+        let index_type = self.infer(index);
+        match target_type {
+            Type::List => self.expect(&Type::Int, &index_type, "List index", index.span()),
+            Type::Map => self.check_key(&index_type, index.span()),
+            Type::Any => {}
+            other => self.error(format!("Cannot index into {}", other), span),
+        }
+        Type::Any
+    }
+
+    // Check a variable, index or field being assigned to, and give the type it holds.
+    fn check_target(&mut self, target: &Expr) -> Type {
+        match target {
+            Expr::Variable(name, _, span) => {
+                if self.binding(name).is_some_and(|b| b.constant) {
+                    self.error(format!("Cannot assign to constant '{}'", name), *span);
+                }
+                self.lookup(name).unwrap_or(Type::Any)
+            }
+            Expr::Index(target, index, span) => self.check_index(target, index, *span),
+            Expr::Field(target, field, span) => self.check_field(target, field, *span),
+            other => self.infer(other),
+        }
+    }
+
+    // Check `target.field` and give the field's type.
+    fn check_field(&mut self, target: &Expr, field: &str, span: Span) -> Type {
+        let target_type = self.infer(target);
+        self.field_type(target_type, field, span)
+    }
+
+    fn field_type(&mut self, target_type: Type, field: &str, span: Span) -> Type {
+        match target_type {
+            Type::Vec2 if field == "x" || field == "y" => Type::Float,
+            Type::Struct(name) => {
+                let found = self.structs.get(&name).and_then(|fields| {
+                    fields
+                        .iter()
+                        .find(|(n, _)| n == field)
+                        .map(|(_, t)| t.clone())
+                });
+                found.unwrap_or_else(|| {
+                    self.error(format!("Struct '{}' has no field '{}'", name, field), span);
+                    Type::Any
+                })
+            }
+            Type::Any => Type::Any,
+            other => {
+                self.error(format!("Cannot read field '{}' of {}", field, other), span);
+                Type::Any
+            }
+        }
+    }
+
+    // Check a constructor gives every field once, with the declared types.
+    fn check_construct(&mut self, name: &str, fields: &[(String, Expr)], span: Span) -> Type {
+        let Some(declared) = self.structs.get(name).cloned() else {
+            for (_, value) in fields {
+                self.infer(value);
+            }
+            self.error(format!("Unknown struct '{}'", name), span);
+            return Type::Any;
+        };
+        for (i, (field, value)) in fields.iter().enumerate() {
+            let found = self.infer(value);
+            if fields[..i].iter().any(|(f, _)| f == field) {
+                self.error(format!("Field '{}' is given twice", field), value.span());
+            }
+            match declared.iter().find(|(n, _)| n == field) {
+                Some((_, typ)) => {
+                    let what = format!("Field '{}' of '{}'", field, name);
+                    self.expect(typ, &found, &what, value.span());
+                }
+                None => self.error(
+                    format!("Struct '{}' has no field '{}'", name, field),
+                    value.span(),
+                ),
+            }
+        }
+        for (field, _) in &declared {
+            if !fields.iter().any(|(f, _)| f == field) {
+                self.error(format!("Missing field '{}' of '{}'", field, name), span);
+            }
+        }
+        Type::Struct(name.to_string())
+    }
+
+    // Check `<enum>::<variant>(<payload>)` against the enum's declaration.
+    fn check_variant(
+        &mut self,
+        enum_name: &str,
+        variant: &str,
+        payload: &[Expr],
+        span: Span,
+    ) -> Type {
+        let found: Vec<Type> = payload.iter().map(|p| self.infer(p)).collect();
+        let Some(variants) = self.enums.get(enum_name).cloned() else {
+            self.error(format!("Unknown enum '{}'", enum_name), span);
+            return Type::Any;
+        };
+        let Some((_, types)) = variants.iter().find(|(n, _)| n == variant) else {
+            self.error(
+                format!("Enum '{}' has no variant '{}'", enum_name, variant),
+                span,
+            );
+            return Type::Any;
+        };
+        if types.len() != found.len() {
+            self.error(
+                format!(
+                    "Variant '{}::{}' holds {} value(s), got {}",
+                    enum_name,
+                    variant,
+                    types.len(),
+                    found.len()
+                ),
+                span,
+            );
+        }
+        for (i, (expected, (value, typ))) in
+            types.iter().zip(payload.iter().zip(&found)).enumerate()
+        {
+            let what = format!("Value {} of '{}::{}'", i + 1, enum_name, variant);
+            self.expect(expected, typ, &what, value.span());
+        }
+        Type::Enum(enum_name.to_string())
+    }
+
+    // Check a pattern can match values of `subject`, collecting what it binds.
+    fn check_pattern(
+        &mut self,
+        pattern: &Pattern,
+        subject: &Type,
+        span: Span,
+        bindings: &mut Vec<(String, Type)>,
+    ) {
+        match pattern {
+            Pattern::Wildcard => {}
+            Pattern::Binding(name) => bindings.push((name.clone(), subject.clone())),
+            Pattern::Literal(value) => {
+                let found = Type::of_value(value);
+                let numbers = subject.is_numeric() && found.is_numeric();
+                if !numbers && !subject.accepts(&found) {
+                    self.error(format!("A {} pattern can't match {}", found, subject), span);
+                }
+            }
+            Pattern::Range(..) => {
+                if !Type::Int.accepts(subject) {
+                    self.error(format!("A range pattern can't match {}", subject), span);
+                }
+            }
+            Pattern::Variant(enum_name, variant, payload) => {
+                // A bare variant name is looked up in the subject's enum, or any enum if unknown.
+                let enum_name = match (enum_name, subject) {
+                    (Some(name), _) => Some(name.clone()),
+                    (None, Type::Enum(name)) => Some(name.clone()),
+                    (None, _) => self
+                        .enums
+                        .iter()
+                        .find(|(_, vs)| vs.iter().any(|(n, _)| n == variant))
+                        .map(|(name, _)| name.clone()),
+                };
+                let found = enum_name.as_ref().and_then(|name| {
+                    let variants = self.enums.get(name)?;
+                    let (_, types) = variants.iter().find(|(n, _)| n == variant)?;
+                    Some((name.clone(), types.clone()))
+                });
+                let Some((enum_name, types)) = found else {
+                    self.error(format!("Unknown enum variant '{}'", variant), span);
+                    for p in payload {
+                        self.check_pattern(p, &Type::Any, span, bindings);
+                    }
+                    return;
+                };
+                let enum_type = Type::Enum(enum_name.clone());
+                if !subject.accepts(&enum_type) {
+                    self.error(
+                        format!("A {} pattern can't match {}", enum_type, subject),
+                        span,
+                    );
+                }
+                if types.len() != payload.len() {
+                    self.error(
+                        format!(
+                            "Variant '{}::{}' holds {} value(s), the pattern has {}",
+                            enum_name,
+                            variant,
+                            types.len(),
+                            payload.len()
+                        ),
+                        span,
+                    );
+                }
+                for (i, p) in payload.iter().enumerate() {
+                    let typ = types.get(i).cloned().unwrap_or(Type::Any);
+                    self.check_pattern(p, &typ, span, bindings);
+                }
+            }
+        }
+    }
+
+    // Report a match that can't handle every value of a known type.
+    fn check_exhaustive(&mut self, subject: &Type, arms: &[MatchArm], span: Span) {
+        if arms.iter().any(|arm| arm.pattern.is_catch_all()) {
+            return;
+        }
+        let missing: Vec<String> = match subject {
+            Type::Any => return,
+            Type::Bool => ["true", "false"]
+                .into_iter()
+                .filter(|b| {
+                    !arms.iter().any(|arm| {
+                        matches!(&arm.pattern, Pattern::Literal(Value::Bool(v)) if v.to_string() == *b)
+                    })
+                })
+                .map(|b| b.to_string())
+                .collect(),
+            Type::Enum(name) => {
+                let variants = self.enums.get(name).cloned().unwrap_or_default();
+                variants
+                    .into_iter()
+                    .filter(|(variant, _)| {
+                        // Only a variant pattern whose payload matches anything covers it.
+                        !arms.iter().any(|arm| match &arm.pattern {
+                            Pattern::Variant(_, v, payload) => {
+                                v == variant && payload.iter().all(Pattern::is_catch_all)
+                            }
+                            _ => false,
+                        })
+                    })
+                    .map(|(variant, _)| variant)
+                    .collect()
+            }
+            _ => vec!["_".to_string()],
+        };
+        if !missing.is_empty() {
+            self.error(
+                format!(
+                    "Match on {} doesn't cover every value, missing: {}",
+                    subject,
+                    missing.join(", ")
+                ),
+                span,
+            );
+        }
+    }
+
+    fn check_key(&mut self, key_type: &Type, span: Span) {
+        if !matches!(key_type, Type::Int | Type::Str | Type::Bool | Type::Any) {
+            self.error(
+                format!("Map keys should be int, str or bool, found {}", key_type),
+                span,
+            );
+        }
+    }
+
+    // Infer the type of an expression, reporting operator misuse along the way.
+    fn infer(&mut self, expr: &Expr) -> Type {
+        match expr {
+            Expr::Literal(v, _) => Type::of_value(v),
+            Expr::Variable(name, _, _) => self.lookup(name).unwrap_or(Type::Any),
+            Expr::UnaryOp(op, operand, span) => {
+                let typ = self.infer(operand);
+                match (op.as_str(), &typ) {
+                    ("!", Type::Bool | Type::Any) => Type::Bool,
+                    ("-", t) if t.is_numeric() || *t == Type::Vec2 => typ,
+                    _ => {
+                        self.error(format!("Cannot apply '{}' to {}", op, typ), *span);
+                        Type::Any
+                    }
+                }
+            }
+            Expr::BinaryOp(left, op, right, span) => {
+                let l = self.infer(left);
+                let r = self.infer(right);
+                match binary_type(op, &l, &r) {
+                    Some(t) => t,
+                    None => {
+                        self.error(format!("Cannot apply '{}' to {} and {}", op, l, r), *span);
+                        Type::Any
+                    }
+                }
+            }
+            Expr::Call(callee, arguments, span) => self.check_call(callee, arguments, *span),
+            Expr::Lambda(decl) => {
+                let sig = self.signature(decl);
+                self.check_function(decl, &sig);
+                Type::Function(Some(sig))
+            }
+            Expr::List(items, _) => {
+                for item in items {
+                    self.infer(item);
+                }
+                Type::List
+            }
+            Expr::Map(entries, _) => {
+                for (key, value) in entries {
+                    let key_type = self.infer(key);
+                    self.check_key(&key_type, key.span());
+                    self.infer(value);
+                }
+                Type::Map
+            }
+            Expr::Index(target, index, span) => self.check_index(target, index, *span),
+            Expr::Construct(name, fields, span) => self.check_construct(name, fields, *span),
+            Expr::Field(target, field, span) => self.check_field(target, field, *span),
+            Expr::Variant(enum_name, variant, payload, span) => {
+                self.check_variant(enum_name, variant, payload, *span)
+            }
+            // Any value can be shown inside a string.
+            Expr::Interpolated(parts, _) => {
+                for part in parts {
+                    self.infer(part);
+                }
+                Type::Str
+            }
+        }
+    }
+}
+
+// Result type of a binary operator, None if the operands don't support it.
+// Mirrors `operators::binary_op`.
+fn binary_type(op: &str, l: &Type, r: &Type) -> Option<Type> {
+    match op {
+        "+" if matches!(
+            (l, r),
+            (Type::Str, Type::Str | Type::Any) | (Type::Any, Type::Str)
+        ) =>
+        {
+            Some(Type::Str)
+        }
+        "+" | "-" | "*" | "/" if *l == Type::Vec2 || *r == Type::Vec2 => match (op, l, r) {
+            ("+" | "-" | "*", Type::Vec2 | Type::Any, Type::Vec2 | Type::Any) => Some(Type::Vec2),
+            ("*" | "/", Type::Vec2, t) | ("*", t, Type::Vec2) if t.is_numeric() => Some(Type::Vec2),
+            _ => None,
+        },
+        "+" | "-" | "*" | "/" | "%" => match (l, r) {
+            (Type::Int, Type::Int) => Some(Type::Int),
+            (Type::Float, t) | (t, Type::Float) if t.is_numeric() => Some(Type::Float),
+            (Type::Any, t) | (t, Type::Any) if t.is_numeric() => Some(Type::Any),
+            _ => None,
+        },
+        "<" | "<=" | ">" | ">=" => match (l, r) {
+            (Type::Str, Type::Str) | (Type::Str, Type::Any) | (Type::Any, Type::Str) => {
+                Some(Type::Bool)
+            }
+            _ if l.is_numeric() && r.is_numeric() => Some(Type::Bool),
+            _ => None,
+        },
+        "==" | "!=" => Some(Type::Bool),
+        "&&" | "||" => match (l, r) {
+            (Type::Bool | Type::Any, Type::Bool | Type::Any) => Some(Type::Bool),
+            _ => None,
+        },
+        // The wrapped value's type isn't known, only the default's.
+        "??" => match l {
+            Type::Optional | Type::Any => Some(Type::Any),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// How an assignment target is named in type errors.
+fn assigned_what(target: &Expr) -> String {
+    match target {
+        Expr::Variable(name, _, _) | Expr::Field(_, name, _) => {
+            format!("Value assigned to '{}'", name)
+        }
+        _ => "Assigned value".to_string(),
+    }
+}
+
+// Whether a block returns on every path through it.
+fn always_returns(body: &[Statement]) -> bool {
+    body.iter().any(|stmt| match stmt {
+        Statement::Return(_) | Statement::Throw(..) => true,
+        Statement::Try {
+            body, catch_body, ..
+        } => always_returns(body) && always_returns(catch_body),
+        Statement::If {
+            then_body,
+            else_body: Some(else_body),
+            ..
+        } => always_returns(then_body) && always_returns(else_body),
+        // A value no arm matches is a runtime error, so the match never falls through.
+        Statement::Match { arms, .. } => {
+            !arms.is_empty() && arms.iter().all(|arm| always_returns(&arm.body))
+        }
+        // `while (true)` only ends through a `break`, anything else leaves the function.
+        Statement::While {
+            label,
+            condition: Expr::Literal(Value::Bool(true), _),
+            body,
+        } => !breaks_out(body, label.as_deref(), true),
+        _ => false,
+    })
+}
+
+// Whether a `break` in `body` leaves the loop labelled `label`. `innermost` is
+// false inside nested loops, where only a labelled break still reaches it.
+fn breaks_out(body: &[Statement], label: Option<&str>, innermost: bool) -> bool {
+    body.iter().any(|stmt| match stmt {
+        Statement::Break(None, _) => innermost,
+        Statement::Break(Some(target), _) => Some(target.as_str()) == label,
+        Statement::If {
+            then_body,
+            else_body,
+            ..
+        } => {
+            breaks_out(then_body, label, innermost)
+                || else_body
+                    .as_ref()
+                    .is_some_and(|body| breaks_out(body, label, innermost))
+        }
+        Statement::Try {
+            body, catch_body, ..
+        } => breaks_out(body, label, innermost) || breaks_out(catch_body, label, innermost),
+        Statement::Match { arms, .. } => arms
+            .iter()
+            .any(|arm| breaks_out(&arm.body, label, innermost)),
+        Statement::While { body, .. } | Statement::ForLoop { body, .. } => {
+            breaks_out(body, label, false)
+        }
+        _ => false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::int::parser::interpreter_parser;
+    use crate::int::pragma::StatementEnd;
+    use crate::int::resolver::resolve_program;
+
+    fn errors(source: &str) -> Vec<String> {
+        let mut stmts = interpreter_parser::program(source, StatementEnd::Semicolon).unwrap();
+        resolve_program(&mut stmts);
+        check_program(&stmts)
+            .into_iter()
+            .map(|e| e.message)
+            .collect()
+    }
+
+    #[test]
+    fn accepts_well_typed_program() {
+        let source = "var x: int = 1;
+            var y: float = x + 0.5;
+            fn add(a: int, b: int) -> int { return a + b; }
+            x = add(x, 2);
+            var names: list = [\"a\", \"b\"];
+            for n in names { print(n); }";
+        assert!(errors(source).is_empty(), "{:?}", errors(source));
+    }
+
+    #[test]
+    fn rejects_mismatched_initializer_and_assignment() {
+        let found = errors("var x: int = \"one\"; var y: bool = true; y = 2;");
+        assert_eq!(found.len(), 2, "{:?}", found);
+        assert!(found[0].contains("'x'"));
+        assert!(found[1].contains("'y'"));
+    }
+
+    #[test]
+    fn rejects_unknown_type() {
+        let found = errors("var p: Point = 1;");
+        assert_eq!(found.len(), 1, "{:?}", found);
+    }
+
+    #[test]
+    fn checks_arguments_and_returns() {
+        let found = errors(
+            "fn half(n: int) -> int { if (n > 0) { return n / 2; } }
+            fn name() -> str { return 1; }
+            half(\"ten\");",
+        );
+        assert_eq!(found.len(), 3, "{:?}", found);
+        assert!(found[0].contains("every path"), "{}", found[0]);
+    }
+
+    #[test]
+    fn match_must_cover_every_variant() {
+        let found = errors(
+            "enum State { Idle, Chasing(int), Fleeing }
+            var s: State = State::Idle;
+            match s { State::Idle => print(1), State::Chasing(t) => print(t) }
+            match s { State::Idle => print(1), _ => print(2) }",
+        );
+        assert_eq!(
+            found,
+            ["Match on State doesn't cover every value, missing: Fleeing"]
+        );
+    }
+
+    #[test]
+    fn constants_cannot_change() {
+        for source in [
+            "const MAX: int = 3; MAX = 4;",
+            "const MAX: int = 3; MAX += 1;",
+            "const MAX: int = 3; var MAX: int = 5;",
+            "const MAX: int = 3; fn f() { global MAX: int = 99; }",
+        ] {
+            let found = errors(source);
+            assert_eq!(found, ["Cannot assign to constant 'MAX'"], "{}", source);
+        }
+    }
+
+    #[test]
+    fn inner_scopes_may_shadow_constants() {
+        assert!(errors("const MAX: int = 3; fn f() { var MAX: int = 7; }").is_empty());
+    }
+
+    #[test]
+    fn ints_fit_where_floats_are_expected() {
+        let source = "struct Ball { x: float }
+            var speed: float = 5;
+            var b: Ball = Ball { x: 1 };
+            var v: vec2 = vec2(1, 2);
+            b.x = 3; v.x = 5; speed += 1;
+            fn half(n: float) -> float { return 1; }
+            half(3);";
+        assert!(errors(source).is_empty(), "{:?}", errors(source));
+        assert_eq!(errors("var n: int = 1.5;").len(), 1);
+    }
+
+    #[test]
+    fn endless_loops_only_exit_by_returning() {
+        let source = "fn f() -> int { while (true) { return 1; } }
+            fn g(n: int) -> int {
+                outer: while (true) {
+                    for i in 0..n { if (i > 3) { break; } }
+                    while (true) { break; }
+                    if (n > 0) { return n; }
+                }
+            }";
+        assert!(errors(source).is_empty(), "{:?}", errors(source));
+        for source in [
+            "fn f(n: int) -> int { while (true) { if (n > 0) { break; } return 1; } }",
+            "fn f() -> int { outer: while (true) { for i in 0..3 { break outer; } } }",
+            "fn f(n: int) -> int { while (n > 0) { return 1; } }",
+        ] {
+            assert_eq!(errors(source).len(), 1, "{}", source);
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use crate::int::lexeme::Span;
+use crate::int::pragma::StatementEnd;
+use peg::error::{ExpectedSet, ParseError};
+use peg::str::LineCol;
+
+// Render a parse failure as the offending line, a caret under the column and
+// the tokens the parser would have accepted there.
+pub fn render_parse_error(source: &str, err: &ParseError<LineCol>, mode: StatementEnd) -> String {
+    let LineCol {
+        mut line,
+        mut column,
+        offset,
+    } = err.location;
+    // Failing at the end of input after trailing blank lines: point at the last code instead.
+    let code_end = source.trim_end().len();
+    if offset > code_end {
+        (line, column) = Span::new(code_end, code_end).line_col(source);
+    }
+    let mut expected = expected_tokens(&err.expected);
+    if err.expected.tokens().any(|t| t == "\";\"") {
+        // Without its ';' a statement runs on into the next line and fails there,
+        // so point at the end of the line that's missing it.
+        let before = source[..offset.min(source.len())].trim_end().len();
+        let (prev_line, prev_column) = Span::new(before, before).line_col(source);
+        if prev_line < line {
+            (line, column) = (prev_line, prev_column);
+            expected = "';'".to_string();
+        }
+        expected.push_str(match mode {
+            StatementEnd::Semicolon => "\nhint: did you forget ';'?",
+            StatementEnd::Newline => "\nhint: statements end at a line break or ';'",
+        });
+    }
+    format!(
+        "parse error at {}:{}\n{}expected {}",
+        line,
+        column,
+        source_excerpt(source, line, column, 1),
+        expected
+    )
+}
+
+// The source line with a gutter, and carets under `width` characters from `column`.
+pub fn source_excerpt(source: &str, line: usize, column: usize, width: usize) -> String {
+    let text = source.lines().nth(line.saturating_sub(1)).unwrap_or("");
+    let gutter = line.to_string();
+    // Keep tabs so the caret lines up with what the editor shows.
+    let indent: String = text
+        .chars()
+        .take(column.saturating_sub(1))
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    format!(
+        "{} | {}\n{} | {}{}\n",
+        gutter,
+        text,
+        " ".repeat(gutter.len()),
+        indent,
+        "^".repeat(width.max(1))
+    )
+}
+
+// Turn peg's raw expected set into a sorted, readable list.
+fn expected_tokens(expected: &ExpectedSet) -> String {
+    let mut tokens: Vec<String> = expected
+        .tokens()
+        // Character classes only show up for whitespace, which is never the real problem,
+        // and the statement-end mode check fails without naming a token.
+        .filter(|t| !t.is_empty() && !t.starts_with('['))
+        .map(
+            |t| match t.strip_prefix('"').and_then(|t| t.strip_suffix('"')) {
+                Some(literal) => format!("'{}'", literal.replace("\\\"", "\"")),
+                None if t == "EOF" => "end of input".to_string(),
+                None => t.to_string(),
+            },
+        )
+        .collect();
+    tokens.sort();
+    tokens.dedup();
+    match tokens.len() {
+        0 => "something else".to_string(),
+        1 => tokens.remove(0),
+        _ => format!("one of: {}", tokens.join(", ")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::int::parser::interpreter_parser;
+
+    fn render(source: &str, mode: StatementEnd) -> String {
+        let err = interpreter_parser::program(source, mode).err().unwrap();
+        render_parse_error(source, &err, mode)
+    }
+
+    #[test]
+    fn shows_the_line_a_caret_and_expected_tokens() {
+        assert_eq!(
+            render("fn f( { }", StatementEnd::Semicolon),
+            "parse error at 1:7\n1 | fn f( { }\n  |       ^\nexpected one of: ')', identifier"
+        );
+    }
+
+    #[test]
+    fn caret_keeps_tabs_from_the_source_line() {
+        let message = render("var x: int = 1;\n\tprint(x +);", StatementEnd::Semicolon);
+        assert!(message.starts_with("parse error at 2:11\n"), "{}", message);
+        assert!(message.contains("\n  | \t         ^\n"), "{}", message);
+        assert!(message.ends_with("expected one of: 'fn', expression, identifier"));
+    }
+
+    #[test]
+    fn missing_semicolon_points_at_the_line_missing_it() {
+        let message = render("print(1)\nprint(2);", StatementEnd::Semicolon);
+        assert!(message.starts_with("parse error at 1:9\n"), "{}", message);
+        assert!(
+            message.contains("expected ';'\nhint: did you forget ';'?"),
+            "{}",
+            message
+        );
+    }
+
+    #[test]
+    fn newline_mode_hint_mentions_line_breaks() {
+        let message = render("print(1) print(2)", StatementEnd::Newline);
+        assert!(
+            message.ends_with("hint: statements end at a line break or ';'"),
+            "{}",
+            message
+        );
+        assert!(message.contains("end of line"), "{}", message);
+        assert!(!message.contains("one of: ,"), "{}", message);
+    }
+}
@@ -0,0 +1,128 @@
+use std::rc::Rc;
+
+use crate::int::diagnostic::source_excerpt;
+use crate::int::lexeme::{Field, Span, StructDecl, Value};
+
+// What went wrong, so the UI (and later `catch`) can tell failures apart.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ErrorKind {
+    UndefinedVariable,
+    UndefinedFunction,
+    TypeMismatch,
+    InvalidOperation,
+    DivisionByZero,
+    ArityMismatch,
+    StackOverflow,
+    IndexOutOfBounds,
+    KeyNotFound,
+    Overflow,
+    Thrown, // raised by the script itself with `throw`
+}
+
+impl std::fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            ErrorKind::UndefinedVariable => "undefined variable",
+            ErrorKind::UndefinedFunction => "undefined function",
+            ErrorKind::TypeMismatch => "type mismatch",
+            ErrorKind::InvalidOperation => "invalid operation",
+            ErrorKind::DivisionByZero => "division by zero",
+            ErrorKind::ArityMismatch => "wrong number of arguments",
+            ErrorKind::StackOverflow => "stack overflow",
+            ErrorKind::IndexOutOfBounds => "index out of bounds",
+            ErrorKind::KeyNotFound => "key not found",
+            ErrorKind::Overflow => "integer overflow",
+            ErrorKind::Thrown => "thrown",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Clone)]
+pub struct RuntimeError {
+    pub kind: ErrorKind,
+    pub message: String,
+    // Natives don't know where they were called from, the interpreter fills it in.
+    pub span: Option<Span>,
+    // What `throw` was given, handed to `catch` as is.
+    pub thrown: Option<Value>,
+}
+
+impl RuntimeError {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        RuntimeError {
+            kind,
+            message: message.into(),
+            span: None,
+            thrown: None,
+        }
+    }
+
+    // Attach a source location unless a more precise one is already set.
+    pub fn at(mut self, span: Span) -> Self {
+        if self.span.is_none() {
+            self.span = Some(span);
+        }
+        self
+    }
+
+    // Format the error with the source line it points at.
+    pub fn render(&self, source: &str) -> String {
+        match self.span {
+            Some(span) => {
+                let (line, column) = span.line_col(source);
+                let width = span.width(source);
+                format!(
+                    "runtime error ({}) at {}:{}: {}\n{}",
+                    self.kind,
+                    line,
+                    column,
+                    self.message,
+                    source_excerpt(source, line, column, width).trim_end()
+                )
+            }
+            None => format!("runtime error ({}): {}", self.kind, self.message),
+        }
+    }
+}
+
+// The built-in struct a `catch` block receives:
+// `Error { message: str, kind: str, line: int, column: int, value: any }`.
+// `value` is what was thrown, or nil for errors raised by the interpreter or natives.
+pub fn error_struct() -> Rc<StructDecl> {
+    let field = |name: &str, typ: &str| Field {
+        name: name.to_string(),
+        type_annotation: typ.to_string(),
+    };
+    Rc::new(StructDecl {
+        name: "Error".to_string(),
+        fields: vec![
+            field("message", "str"),
+            field("kind", "str"),
+            field("line", "int"),
+            field("column", "int"),
+            field("value", "any"),
+        ],
+        span: Span::default(),
+    })
+}
+
+// A mismatch found by the type checker before the script runs.
+#[derive(Clone, Debug)]
+pub struct TypeError {
+    pub message: String,
+    pub span: Span,
+}
+
+impl TypeError {
+    pub fn render(&self, source: &str) -> String {
+        let (line, column) = self.span.line_col(source);
+        format!(
+            "type error at {}:{}: {}\n{}",
+            line,
+            column,
+            self.message,
+            source_excerpt(source, line, column, self.span.width(source)).trim_end()
+        )
+    }
+}
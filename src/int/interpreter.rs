@@ -1,239 +1,1263 @@
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::rc::Rc;
 
 use bevy::ecs::event::EventWriter;
 use bevy::ecs::system::Commands;
 
+use crate::int::checker::{Type, check_program};
+use crate::int::diagnostic::render_parse_error;
+use crate::int::error::{ErrorKind, RuntimeError, error_struct};
 use crate::int::lexeme::*;
+use crate::int::operators::{
+    IntMode, assign_index, binary_op, index_op, set_vec2_field, unary_op, values_equal, vec2_field,
+};
 use crate::int::parser::interpreter_parser;
+use crate::int::pragma::{Pragmas, read_pragmas};
+use crate::int::resolver::resolve_program;
 use crate::ui::resources::{PrintEvent, SpawnEvent};
 
-use super::native::build_native_fn_table;
+use super::native::{build_native_const_table, build_native_fn_table};
 
 pub struct Interpreter {
-    // A stack of scopes; the last is the current environment.
-    pub scopes: Vec<HashMap<String, Value>>,
-    // Function definitions.
-    pub functions: HashMap<String, FunctionDef>,
+    // Top-level variables and natives, looked up by name.
+    pub globals: HashMap<String, Value>,
+    // Type annotations of the top-level variables that were declared with one.
+    pub global_annotations: HashMap<String, String>,
+    // Innermost local environment, None while running top-level code.
+    pub env: Option<Rc<RefCell<Env>>>,
+    // Nested user function calls currently running, and how deep they may go
+    // before the script is stopped instead of overflowing the Rust stack.
+    pub call_depth: usize,
+    pub max_call_depth: usize,
+    // Struct types declared so far, by name.
+    pub structs: HashMap<String, Rc<StructDecl>>,
+    // Enum types declared so far, by name.
+    pub enums: HashMap<String, Rc<EnumDecl>>,
+    // How integer arithmetic handles overflow, chosen with `#!overflow`.
+    pub int_mode: IntMode,
+    // Script text, so caught errors can tell their line and column.
+    pub source: String,
 }
 
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 256;
+// Stack for the default depth; a deeper `#!max_depth` gets proportionally more.
+const INTERPRETER_STACK_SIZE: usize = 64 * 1024 * 1024;
+
 impl Interpreter {
     pub fn new() -> Self {
         let native_fns = build_native_fn_table();
         let mut interp = Interpreter {
-            scopes: vec![HashMap::new()],
-            functions: HashMap::new(),
+            globals: HashMap::new(),
+            global_annotations: HashMap::new(),
+            env: None,
+            call_depth: 0,
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            structs: HashMap::from([("Error".to_string(), error_struct())]),
+            enums: HashMap::new(),
+            int_mode: IntMode::default(),
+            source: String::new(),
         };
         for (name, func) in native_fns.iter() {
-            interp.set_var(name.clone(), Value::Native(*func));
+            interp.declare_var(name.clone(), Value::Native(*func), None);
+        }
+        for (name, value) in build_native_const_table() {
+            interp.declare_var(name, value, None);
         }
         interp
     }
 
     fn push_scope(&mut self) {
-        self.scopes.push(HashMap::new());
+        self.env = Some(Rc::new(RefCell::new(Env {
+            values: Vec::new(),
+            annotations: Vec::new(),
+            parent: self.env.take(),
+        })));
     }
 
     fn pop_scope(&mut self) {
-        self.scopes.pop();
+        self.env = self.env.take().and_then(|env| env.borrow().parent.clone());
+    }
+
+    // Introduce a new binding in the current scope, shadowing any outer one.
+    // Locals get the next index, which is the one the resolver handed out.
+    // The annotation, if any, is checked again by every later assignment.
+    fn declare_var(&mut self, name: String, value: Value, annotation: Option<&str>) {
+        match &self.env {
+            Some(env) => {
+                let mut env = env.borrow_mut();
+                env.values.push(value);
+                env.annotations.push(annotation.map(str::to_string));
+            }
+            None => self.declare_global(name, value, annotation),
+        }
     }
 
-    fn set_var(&mut self, name: String, value: Value) {
-        if let Some(scope) = self.scopes.last_mut() {
-            scope.insert(name, value);
+    fn declare_global(&mut self, name: String, value: Value, annotation: Option<&str>) {
+        match annotation {
+            Some(annotation) => self
+                .global_annotations
+                .insert(name.clone(), annotation.to_string()),
+            None => self.global_annotations.remove(&name),
+        };
+        self.globals.insert(name, value);
+    }
+
+    // The environment `depth` levels above the current one.
+    fn env_at(&self, depth: usize) -> Option<Rc<RefCell<Env>>> {
+        let mut env = self.env.clone();
+        for _ in 0..depth {
+            env = env?.borrow().parent.clone();
         }
+        env
     }
 
-    // Look up a variable by searching from innermost to outermost scope.
-    fn get_var(&self, name: &str) -> Option<Value> {
-        for scope in self.scopes.iter().rev() {
-            if let Some(val) = scope.get(name) {
-                return Some(val.clone());
+    // Update the binding the resolver picked for this assignment. Values the
+    // checker can't see through, like a call to an unannotated function, are
+    // held to the variable's annotation here.
+    fn assign_var(&mut self, name: &str, slot: Slot, value: Value) -> Result<(), RuntimeError> {
+        match slot {
+            Slot::Global => {
+                if let Some(target) = self.globals.get_mut(name) {
+                    *target = match self.global_annotations.get(name) {
+                        Some(annotation) => check_annotation(value, annotation)?,
+                        None => value,
+                    };
+                    return Ok(());
+                }
+            }
+            Slot::Local(depth, index) => {
+                if let Some(env) = self.env_at(depth) {
+                    let mut env = env.borrow_mut();
+                    let value = match env.annotations.get(index) {
+                        Some(Some(annotation)) => check_annotation(value, annotation)?,
+                        _ => value,
+                    };
+                    if let Some(target) = env.values.get_mut(index) {
+                        *target = value;
+                        return Ok(());
+                    }
+                }
             }
         }
-        None
+        Err(RuntimeError::new(
+            ErrorKind::UndefinedVariable,
+            format!(
+                "Cannot assign to '{}' before it is declared with 'var'",
+                name
+            ),
+        ))
+    }
+
+    // Read the binding the resolver picked for this reference.
+    fn get_var(&self, name: &str, slot: Slot) -> Option<Value> {
+        match slot {
+            Slot::Global => self.globals.get(name).cloned(),
+            Slot::Local(depth, index) => self.env_at(depth)?.borrow().values.get(index).cloned(),
+        }
     }
 
     // Evaluate an expression.
-    fn eval_expr(&self, expr: &Expr) -> Option<Value> {
+    fn eval_expr(
+        &mut self,
+        expr: &Expr,
+        writer_print: &mut EventWriter<PrintEvent>,
+        writer_spawn: &mut EventWriter<SpawnEvent>,
+        commands: &mut Commands,
+    ) -> Result<Value, RuntimeError> {
         match expr {
-            Expr::Literal(v) => Some(v.clone()),
-            Expr::Variable(name) => self.get_var(name),
-            Expr::BinaryOp(left, op, right) => {
-                let left_val = self.eval_expr(left)?;
-                let right_val = self.eval_expr(right)?;
-                match (left_val, right_val, op.as_str()) {
-                    (Value::Int(l), Value::Int(r), "+") => Some(Value::Int(l + r)),
-                    (Value::Int(l), Value::Int(r), "<") => Some(Value::Bool(l < r)),
-                    // Extend with additional operators and type handling as needed.
-                    _ => None,
+            Expr::Literal(v, _) => Ok(v.clone()),
+            Expr::Variable(name, slot, span) => self.get_var(name, *slot).ok_or_else(|| {
+                RuntimeError::new(
+                    ErrorKind::UndefinedVariable,
+                    format!("Variable '{}' not found!", name),
+                )
+                .at(*span)
+            }),
+            Expr::BinaryOp(left, op, right, span) => {
+                let left_val = self.eval_expr(left, writer_print, writer_spawn, commands)?;
+                // `&&` and `||` only evaluate the right side when they need it.
+                match (op.as_str(), &left_val) {
+                    ("&&", Value::Bool(false)) => return Ok(Value::Bool(false)),
+                    ("||", Value::Bool(true)) => return Ok(Value::Bool(true)),
+                    ("??", Value::Optional(Some(v))) => return Ok(v.as_ref().clone()),
+                    _ => {}
+                }
+                let right_val = self.eval_expr(right, writer_print, writer_spawn, commands)?;
+                binary_op(op, left_val, right_val, self.int_mode).map_err(|e| e.at(*span))
+            }
+            Expr::UnaryOp(op, operand, span) => {
+                let val = self.eval_expr(operand, writer_print, writer_spawn, commands)?;
+                unary_op(op, val, self.int_mode).map_err(|e| e.at(*span))
+            }
+            Expr::Call(callee, arguments, span) => self.eval_call(
+                callee,
+                arguments,
+                *span,
+                writer_print,
+                writer_spawn,
+                commands,
+            ),
+            // Capture the current environment, so the body sees the variables around it.
+            Expr::Lambda(decl) => Ok(Value::Function(Rc::new(FunctionDef {
+                decl: decl.clone(),
+                closure: self.env.clone(),
+            }))),
+            Expr::List(items, _) => {
+                let mut values = Vec::new();
+                for item in items {
+                    values.push(self.eval_expr(item, writer_print, writer_spawn, commands)?);
+                }
+                Ok(Value::List(Rc::new(RefCell::new(values.into()))))
+            }
+            Expr::Map(entries, _) => {
+                let map = Value::Map(Rc::default());
+                for (key, value) in entries {
+                    let key_val = self.eval_expr(key, writer_print, writer_spawn, commands)?;
+                    let val = self.eval_expr(value, writer_print, writer_spawn, commands)?;
+                    // A repeated key keeps its first position but takes the later value.
+                    assign_index(&map, &key_val, val).map_err(|e| e.at(key.span()))?;
+                }
+                Ok(map)
+            }
+            Expr::Construct(name, fields, span) => {
+                let decl = self.structs.get(name).cloned().ok_or_else(|| {
+                    RuntimeError::new(
+                        ErrorKind::UndefinedVariable,
+                        format!("Struct '{}' not found!", name),
+                    )
+                    .at(*span)
+                })?;
+                let mut values: Vec<Option<Value>> = vec![None; decl.fields.len()];
+                for (field, expr) in fields {
+                    let val = self.eval_expr(expr, writer_print, writer_spawn, commands)?;
+                    let i = decl
+                        .field_index(field)
+                        .ok_or_else(|| no_field(&decl.name, field).at(expr.span()))?;
+                    let val = check_annotation(val, &decl.fields[i].type_annotation)
+                        .map_err(|e| e.at(expr.span()))?;
+                    values[i] = Some(val);
                 }
+                let values = values
+                    .into_iter()
+                    .zip(&decl.fields)
+                    .map(|(val, field)| {
+                        val.ok_or_else(|| {
+                            RuntimeError::new(
+                                ErrorKind::InvalidOperation,
+                                format!("Missing field '{}' of '{}'", field.name, decl.name),
+                            )
+                            .at(*span)
+                        })
+                    })
+                    .collect::<Result<Vec<Value>, RuntimeError>>()?;
+                Ok(Value::Struct(Rc::new(StructValue {
+                    decl,
+                    values: RefCell::new(values),
+                })))
+            }
+            Expr::Variant(enum_name, variant, payload, span) => {
+                let decl = self.enums.get(enum_name).cloned().ok_or_else(|| {
+                    RuntimeError::new(
+                        ErrorKind::UndefinedVariable,
+                        format!("Enum '{}' not found!", enum_name),
+                    )
+                    .at(*span)
+                })?;
+                let index = decl
+                    .variant_index(variant)
+                    .ok_or_else(|| no_variant(enum_name, variant).at(*span))?;
+                let types = &decl.variants[index].payload;
+                if types.len() != payload.len() {
+                    return Err(RuntimeError::new(
+                        ErrorKind::ArityMismatch,
+                        format!(
+                            "Variant '{}::{}' holds {} value(s), got {}",
+                            enum_name,
+                            variant,
+                            types.len(),
+                            payload.len()
+                        ),
+                    )
+                    .at(*span));
+                }
+                let mut values = Vec::new();
+                for (expr, annotation) in payload.iter().zip(types) {
+                    let val = self.eval_expr(expr, writer_print, writer_spawn, commands)?;
+                    let val = check_annotation(val, annotation).map_err(|e| e.at(expr.span()))?;
+                    values.push(val);
+                }
+                Ok(Value::Enum(Rc::new(EnumValue {
+                    decl,
+                    variant: index,
+                    payload: values,
+                })))
+            }
+            Expr::Interpolated(parts, _) => {
+                let mut text = String::new();
+                for part in parts {
+                    let val = self.eval_expr(part, writer_print, writer_spawn, commands)?;
+                    text.push_str(&val.to_string());
+                }
+                Ok(Value::Str(text))
+            }
+            Expr::Field(target, field, span) => {
+                let target_val = self.eval_expr(target, writer_print, writer_spawn, commands)?;
+                if let Value::Vec2(v) = target_val {
+                    return vec2_field(v, field).map_err(|e| e.at(*span));
+                }
+                let (instance, i) = struct_field(&target_val, field).map_err(|e| e.at(*span))?;
+                Ok(instance.values.borrow()[i].clone())
+            }
+            Expr::Index(target, index, span) => {
+                let target_val = self.eval_expr(target, writer_print, writer_spawn, commands)?;
+                let index_val = self.eval_expr(index, writer_print, writer_spawn, commands)?;
+                index_op(&target_val, &index_val).map_err(|e| e.at(*span))
             }
         }
     }
 
+    // Evaluate a condition of an if or while, which has to be a bool.
+    fn eval_condition(
+        &mut self,
+        condition: &Expr,
+        writer_print: &mut EventWriter<PrintEvent>,
+        writer_spawn: &mut EventWriter<SpawnEvent>,
+        commands: &mut Commands,
+    ) -> Result<bool, RuntimeError> {
+        match self.eval_expr(condition, writer_print, writer_spawn, commands)? {
+            Value::Bool(b) => Ok(b),
+            other => Err(RuntimeError::new(
+                ErrorKind::TypeMismatch,
+                format!("Condition must be bool, got {}", other.type_name()),
+            )
+            .at(condition.span())),
+        }
+    }
+
+    // Run a block's statements until one of them leaves it early.
+    fn eval_block(
+        &mut self,
+        body: &[Statement],
+        writer_print: &mut EventWriter<PrintEvent>,
+        writer_spawn: &mut EventWriter<SpawnEvent>,
+        commands: &mut Commands,
+    ) -> Result<Flow, RuntimeError> {
+        for s in body {
+            match self.eval_statement(s, writer_print, writer_spawn, commands)? {
+                Flow::Normal => {}
+                flow => return Ok(flow),
+            }
+        }
+        Ok(Flow::Normal)
+    }
+
     // Evaluate a function call.
     fn eval_function_call(
         &mut self,
-        func: FunctionDef,
+        func: &FunctionDef,
         arg_values: Vec<Value>,
         writer_print: &mut EventWriter<PrintEvent>,
         writer_spawn: &mut EventWriter<SpawnEvent>,
         commands: &mut Commands,
-    ) -> Option<Value> {
-        self.push_scope();
-        // Bind parameters.
-        for (param, arg_val) in func.parameters.iter().zip(arg_values.into_iter()) {
-            self.set_var(param.clone(), arg_val);
+    ) -> Result<Value, RuntimeError> {
+        // Arguments from untyped code still have to fit the annotated parameters.
+        let arg_values = arg_values
+            .into_iter()
+            .zip(&func.decl.parameters)
+            .map(|(value, param)| match &param.type_annotation {
+                Some(annotation) => check_annotation(value, annotation),
+                None => Ok(value),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if self.call_depth >= self.max_call_depth {
+            return Err(RuntimeError::new(
+                ErrorKind::StackOverflow,
+                format!("Maximum call depth of {} exceeded", self.max_call_depth),
+            ));
         }
+        self.call_depth += 1;
+        // The body runs in a fresh environment on top of the one the function was
+        // defined in, not the caller's, so it can't see or clobber the caller's locals.
+        let caller_env = self.env.replace(Rc::new(RefCell::new(Env {
+            values: arg_values,
+            annotations: func
+                .decl
+                .parameters
+                .iter()
+                .map(|param| param.type_annotation.clone())
+                .collect(),
+            parent: func.closure.clone(),
+        })));
         // Execute the function body.
-        let mut ret_val = None;
-        for s in &func.body {
-            if let Some(v) = self.eval_statement(s, writer_print, writer_spawn, commands) {
-                ret_val = Some(v);
-                break;
+        let flow = self.eval_block(&func.decl.body, writer_print, writer_spawn, commands);
+        self.env = caller_env;
+        self.call_depth -= 1;
+        match flow? {
+            // Falling off the end of the body gives nil.
+            Flow::Normal => Ok(Value::Unit),
+            Flow::Return(v) => match &func.decl.return_type {
+                Some(annotation) => check_annotation(v, annotation),
+                None => Ok(v),
+            },
+            Flow::Break(label, span) | Flow::Continue(label, span) => Err(stray_jump(label, span)),
+        }
+    }
+
+    // Call whatever the callee evaluates to, native or user function.
+    fn eval_call(
+        &mut self,
+        callee: &Expr,
+        arguments: &[Expr],
+        span: Span,
+        writer_print: &mut EventWriter<PrintEvent>,
+        writer_spawn: &mut EventWriter<SpawnEvent>,
+        commands: &mut Commands,
+    ) -> Result<Value, RuntimeError> {
+        let func = match callee {
+            // Calling an unknown name reads better as a missing function than a missing variable.
+            Expr::Variable(name, slot, _) => self.get_var(name, *slot).ok_or_else(|| {
+                RuntimeError::new(
+                    ErrorKind::UndefinedFunction,
+                    format!("Function '{}' not found!", name),
+                )
+                .at(span)
+            })?,
+            _ => self.eval_expr(callee, writer_print, writer_spawn, commands)?,
+        };
+
+        let mut arg_values = Vec::new();
+        for arg in arguments {
+            arg_values.push(self.eval_expr(arg, writer_print, writer_spawn, commands)?);
+        }
+
+        match func {
+            Value::Native(func) => {
+                func(arg_values, writer_print, writer_spawn, commands).map_err(|e| e.at(span))
             }
+            Value::Function(func) => {
+                let decl = &func.decl;
+                if decl.parameters.len() != arg_values.len() {
+                    return Err(RuntimeError::new(
+                        ErrorKind::ArityMismatch,
+                        format!(
+                            "Function '{}' expects {} argument(s), got {}",
+                            decl.display_name(),
+                            decl.parameters.len(),
+                            arg_values.len()
+                        ),
+                    )
+                    .at(span));
+                }
+                self.eval_function_call(&func, arg_values, writer_print, writer_spawn, commands)
+                    .map_err(|e| e.at(span))
+            }
+            other => Err(RuntimeError::new(
+                ErrorKind::TypeMismatch,
+                format!("Cannot call a value of type {}", other.type_name()),
+            )
+            .at(span)),
         }
-        self.pop_scope();
-        ret_val
     }
 
-    // Evaluate a statement. Returns Some(value) if a return statement is encountered.
+    // Values a for loop walks over. Collections are copied first, so the body
+    // can change them without upsetting the loop.
+    fn loop_items(
+        &mut self,
+        iterable: &Iterable,
+        writer_print: &mut EventWriter<PrintEvent>,
+        writer_spawn: &mut EventWriter<SpawnEvent>,
+        commands: &mut Commands,
+    ) -> Result<Box<dyn Iterator<Item = Value>>, RuntimeError> {
+        match iterable {
+            Iterable::Range {
+                start,
+                end,
+                inclusive,
+                step,
+            } => {
+                let mut bound = |expr: &Expr| -> Result<i64, RuntimeError> {
+                    match self.eval_expr(expr, writer_print, writer_spawn, commands)? {
+                        Value::Int(n) => Ok(n),
+                        other => Err(RuntimeError::new(
+                            ErrorKind::TypeMismatch,
+                            format!("Range bound should be int, found {}", other.type_name()),
+                        )
+                        .at(expr.span())),
+                    }
+                };
+                let start = bound(start)?;
+                let end = bound(end)?;
+                let step = match step {
+                    Some(expr) => match bound(expr)? {
+                        n if n > 0 => n as usize,
+                        n => {
+                            return Err(RuntimeError::new(
+                                ErrorKind::InvalidOperation,
+                                format!("Range step should be positive, found {}", n),
+                            )
+                            .at(expr.span()));
+                        }
+                    },
+                    None => 1,
+                };
+                let values: Box<dyn Iterator<Item = i64>> = match (start <= end, *inclusive) {
+                    (true, false) => Box::new((start..end).step_by(step)),
+                    (true, true) => Box::new((start..=end).step_by(step)),
+                    (false, false) => Box::new((end + 1..=start).rev().step_by(step)),
+                    (false, true) => Box::new((end..=start).rev().step_by(step)),
+                };
+                Ok(Box::new(values.map(Value::Int)))
+            }
+            Iterable::Collection(expr) => {
+                match self.eval_expr(expr, writer_print, writer_spawn, commands)? {
+                    Value::List(items) => Ok(Box::new(items.borrow().to_vec().into_iter())),
+                    Value::Map(entries) => {
+                        let keys: Vec<Value> =
+                            entries.borrow().iter().map(|(k, _)| k.clone()).collect();
+                        Ok(Box::new(keys.into_iter()))
+                    }
+                    Value::Str(s) => {
+                        let chars: Vec<Value> =
+                            s.chars().map(|c| Value::Str(c.to_string())).collect();
+                        Ok(Box::new(chars.into_iter()))
+                    }
+                    other => Err(RuntimeError::new(
+                        ErrorKind::TypeMismatch,
+                        format!("Cannot iterate over {}", other.type_name()),
+                    )
+                    .at(expr.span())),
+                }
+            }
+        }
+    }
+
+    // Evaluate a statement and report how it finished.
     fn eval_statement(
         &mut self,
         stmt: &Statement,
         writer_print: &mut EventWriter<PrintEvent>,
         writer_spawn: &mut EventWriter<SpawnEvent>,
         commands: &mut Commands,
-    ) -> Option<Value> {
+    ) -> Result<Flow, RuntimeError> {
         match stmt {
             Statement::VarDecl(var) => {
-                self.set_var(var.name.clone(), var.value.clone());
-                None
-            }
-            Statement::PrintExpr(expr) => {
-                if let Some(val) = self.eval_expr(expr) {
-                    // send event to bevy
-                    writer_print.send(PrintEvent {
-                        message: format!("{}", val),
-                    });
+                let value = match &var.initializer {
+                    Some(expr) => self.eval_expr(expr, writer_print, writer_spawn, commands)?,
+                    None => zero_value(&var.type_annotation).ok_or_else(|| {
+                        RuntimeError::new(
+                            ErrorKind::TypeMismatch,
+                            format!(
+                                "Variable '{}' of type '{}' needs an initial value",
+                                var.name, var.type_annotation
+                            ),
+                        )
+                        .at(var.span)
+                    })?,
+                };
+                // Values from natives or unannotated parameters aren't known statically.
+                let value =
+                    check_annotation(value, &var.type_annotation).map_err(|e| e.at(var.span))?;
+                if var.kind == VarKind::Global {
+                    self.declare_global(var.name.clone(), value, Some(&var.type_annotation));
                 } else {
-                    println!("error evaluating print statement");
+                    self.declare_var(var.name.clone(), value, Some(&var.type_annotation));
                 }
-                None
+                Ok(Flow::Normal)
+            }
+            Statement::PrintExpr(expr) => {
+                let val = self.eval_expr(expr, writer_print, writer_spawn, commands)?;
+                // send event to bevy
+                writer_print.send(PrintEvent {
+                    message: format!("{}", val),
+                    is_error: false,
+                });
+                Ok(Flow::Normal)
             }
             Statement::ForLoop {
+                label,
                 var_name,
-                start,
-                end,
+                iterable,
                 body,
             } => {
-                for i in *start..*end {
+                let items = self.loop_items(iterable, writer_print, writer_spawn, commands)?;
+                for item in items {
                     self.push_scope();
-                    self.set_var(var_name.clone(), Value::Int(i));
-                    for s in body {
-                        if let Some(ret_val) =
-                            self.eval_statement(s, writer_print, writer_spawn, commands)
-                        {
-                            self.pop_scope();
-                            return Some(ret_val);
-                        }
-                    }
+                    self.declare_var(var_name.clone(), item, None);
+                    let flow = self.eval_block(body, writer_print, writer_spawn, commands);
                     self.pop_scope();
+                    match flow? {
+                        Flow::Break(target, _) if targets(label, &target) => break,
+                        Flow::Continue(target, _) if targets(label, &target) => continue,
+                        Flow::Normal => {}
+                        flow => return Ok(flow),
+                    }
                 }
-                None
+                Ok(Flow::Normal)
             }
-            Statement::While { condition, body } => {
+            Statement::While {
+                label,
+                condition,
+                body,
+            } => {
                 // Continue while condition evaluates to true.
-                while let Some(Value::Bool(true)) = self.eval_expr(condition) {
-                    for s in body {
-                        if let Some(ret_val) =
-                            self.eval_statement(s, writer_print, writer_spawn, commands)
-                        {
-                            return Some(ret_val);
-                        }
+                while self.eval_condition(condition, writer_print, writer_spawn, commands)? {
+                    self.push_scope();
+                    let flow = self.eval_block(body, writer_print, writer_spawn, commands);
+                    self.pop_scope();
+                    match flow? {
+                        Flow::Break(target, _) if targets(label, &target) => break,
+                        Flow::Continue(target, _) if targets(label, &target) => continue,
+                        Flow::Normal => {}
+                        flow => return Ok(flow),
                     }
                 }
-                None
+                Ok(Flow::Normal)
             }
-            Statement::Function {
-                name,
-                parameters,
-                body,
+            Statement::If {
+                condition,
+                then_body,
+                else_body,
             } => {
-                self.functions.insert(
-                    name.clone(),
-                    FunctionDef {
-                        parameters: parameters.clone(),
-                        body: body.clone(),
-                    },
-                );
-                println!("Defined function: {}", name);
-                None
-            }
-            Statement::FunctionCall { name, arguments } => {
-                let mut arg_values = Vec::new();
-                for arg in arguments {
-                    if let Some(val) = self.eval_expr(arg) {
-                        arg_values.push(val);
+                let body =
+                    if self.eval_condition(condition, writer_print, writer_spawn, commands)? {
+                        then_body
+                    } else if let Some(else_body) = else_body {
+                        else_body
                     } else {
-                        println!("Error evaluating argument for function call.");
-                        return None;
+                        return Ok(Flow::Normal);
+                    };
+                self.push_scope();
+                let flow = self.eval_block(body, writer_print, writer_spawn, commands);
+                self.pop_scope();
+                flow
+            }
+            Statement::Function(decl) => {
+                let func = FunctionDef {
+                    decl: decl.clone(),
+                    closure: self.env.clone(),
+                };
+                let name = decl.display_name().to_string();
+                self.declare_var(name, Value::Function(Rc::new(func)), None);
+                Ok(Flow::Normal)
+            }
+            Statement::Struct(decl) => {
+                self.structs.insert(decl.name.clone(), decl.clone());
+                Ok(Flow::Normal)
+            }
+            Statement::Enum(decl) => {
+                self.enums.insert(decl.name.clone(), decl.clone());
+                Ok(Flow::Normal)
+            }
+            Statement::Match { subject, arms } => {
+                let value = self.eval_expr(subject, writer_print, writer_spawn, commands)?;
+                for arm in arms {
+                    let mut bound = Vec::new();
+                    if !match_pattern(&arm.pattern, &value, &mut bound) {
+                        continue;
+                    }
+                    self.push_scope();
+                    for val in bound {
+                        self.declare_var(String::new(), val, None);
                     }
+                    let flow = self.eval_block(&arm.body, writer_print, writer_spawn, commands);
+                    self.pop_scope();
+                    return flow;
                 }
-
-                if let Some(Value::Native(func)) = self.get_var(name) {
-                    return func(arg_values, writer_print, writer_spawn, commands);
+                Err(RuntimeError::new(
+                    ErrorKind::InvalidOperation,
+                    format!("No match arm matches {}", value),
+                )
+                .at(subject.span()))
+            }
+            Statement::Try {
+                body, catch_body, ..
+            } => {
+                // A failure can leave calls and scopes half-finished, so note where to unwind to.
+                let env = self.env.clone();
+                let call_depth = self.call_depth;
+                self.push_scope();
+                let result = self.eval_block(body, writer_print, writer_spawn, commands);
+                self.pop_scope();
+                match result {
+                    Err(error) => {
+                        self.env = env;
+                        self.call_depth = call_depth;
+                        let caught = self.caught_error(error);
+                        self.push_scope();
+                        self.declare_var(String::new(), caught, None);
+                        let flow =
+                            self.eval_block(catch_body, writer_print, writer_spawn, commands);
+                        self.pop_scope();
+                        flow
+                    }
+                    flow => flow,
                 }
-
-                if let Some(func) = self.functions.get(name).cloned() {
-                    self.eval_function_call(func, arg_values, writer_print, writer_spawn, commands)
-                } else {
-                    println!("Function '{}' not found!", name);
-                    None
+            }
+            Statement::Throw(expr, span) => {
+                let value = self.eval_expr(expr, writer_print, writer_spawn, commands)?;
+                let message = match &value {
+                    Value::Str(s) => s.clone(),
+                    // Rethrowing a caught error keeps its message.
+                    Value::Struct(instance) if instance.decl.name == "Error" => {
+                        instance.values.borrow()[0].to_string()
+                    }
+                    other => other.to_string(),
+                };
+                let mut error = RuntimeError::new(ErrorKind::Thrown, message).at(*span);
+                error.thrown = Some(value);
+                Err(error)
+            }
+            Statement::Expression(expr) => {
+                // Calls without a result are fine here, the value is discarded anyway.
+                match expr {
+                    Expr::Call(callee, arguments, span) => {
+                        self.eval_call(
+                            callee,
+                            arguments,
+                            *span,
+                            writer_print,
+                            writer_spawn,
+                            commands,
+                        )?;
+                    }
+                    _ => {
+                        self.eval_expr(expr, writer_print, writer_spawn, commands)?;
+                    }
                 }
+                Ok(Flow::Normal)
             }
-            Statement::Assignment { name, expr } => {
-                if let Some(val) = self.eval_expr(expr) {
-                    self.set_var(name.clone(), val);
-                } else {
-                    println!("Error evaluating assignment expression.");
+            Statement::Assignment {
+                name,
+                slot,
+                expr,
+                span,
+            } => {
+                let val = self.eval_expr(expr, writer_print, writer_spawn, commands)?;
+                self.assign_var(name, *slot, val).map_err(|e| e.at(*span))?;
+                Ok(Flow::Normal)
+            }
+            Statement::IndexAssignment {
+                target,
+                index,
+                expr,
+                span,
+            } => {
+                let target_val = self.eval_expr(target, writer_print, writer_spawn, commands)?;
+                let index_val = self.eval_expr(index, writer_print, writer_spawn, commands)?;
+                let val = self.eval_expr(expr, writer_print, writer_spawn, commands)?;
+                assign_index(&target_val, &index_val, val).map_err(|e| e.at(*span))?;
+                Ok(Flow::Normal)
+            }
+            Statement::FieldAssignment {
+                target,
+                field,
+                expr,
+                span,
+            } => {
+                let place = self
+                    .field_place(target, field, writer_print, writer_spawn, commands)
+                    .map_err(|e| e.at(*span))?;
+                let val = self.eval_expr(expr, writer_print, writer_spawn, commands)?;
+                self.write_place(place, val).map_err(|e| e.at(*span))?;
+                Ok(Flow::Normal)
+            }
+            Statement::CompoundAssignment {
+                target,
+                op,
+                expr,
+                span,
+            } => {
+                // The target is only evaluated once, so `xs[next()] += 1` calls next() once.
+                let place = self
+                    .eval_place(target, writer_print, writer_spawn, commands)
+                    .map_err(|e| e.at(*span))?;
+                let current = self.read_place(&place).map_err(|e| e.at(*span))?;
+                let rhs = self.eval_expr(expr, writer_print, writer_spawn, commands)?;
+                let val = binary_op(op, current, rhs, self.int_mode).map_err(|e| e.at(*span))?;
+                self.write_place(place, val).map_err(|e| e.at(*span))?;
+                Ok(Flow::Normal)
+            }
+            Statement::MultiAssignment {
+                targets, values, ..
+            } => {
+                let mut vals = Vec::new();
+                for value in values {
+                    vals.push(self.eval_expr(value, writer_print, writer_spawn, commands)?);
                 }
-                None
+                for (target, val) in targets.iter().zip(vals) {
+                    let place = self
+                        .eval_place(target, writer_print, writer_spawn, commands)
+                        .map_err(|e| e.at(target.span()))?;
+                    self.write_place(place, val)
+                        .map_err(|e| e.at(target.span()))?;
+                }
+                Ok(Flow::Normal)
+            }
+            Statement::Return(expr) => Ok(Flow::Return(self.eval_expr(
+                expr,
+                writer_print,
+                writer_spawn,
+                commands,
+            )?)),
+            Statement::Break(label, span) => Ok(Flow::Break(label.clone(), *span)),
+            Statement::Continue(label, span) => Ok(Flow::Continue(label.clone(), *span)),
+        }
+    }
+
+    // Find what an assignment target refers to, evaluating its parts once.
+    fn eval_place(
+        &mut self,
+        target: &Expr,
+        writer_print: &mut EventWriter<PrintEvent>,
+        writer_spawn: &mut EventWriter<SpawnEvent>,
+        commands: &mut Commands,
+    ) -> Result<Place, RuntimeError> {
+        match target {
+            Expr::Variable(name, slot, _) => Ok(Place::Var(name.clone(), *slot)),
+            Expr::Index(target, index, _) => {
+                let target_val = self.eval_expr(target, writer_print, writer_spawn, commands)?;
+                let index_val = self.eval_expr(index, writer_print, writer_spawn, commands)?;
+                Ok(Place::Index(target_val, index_val))
+            }
+            Expr::Field(target, field, _) => {
+                self.field_place(target, field, writer_print, writer_spawn, commands)
+            }
+            _ => Err(RuntimeError::new(
+                ErrorKind::InvalidOperation,
+                "Only variables, indexes and fields can be assigned to",
+            )),
+        }
+    }
+
+    fn field_place(
+        &mut self,
+        target: &Expr,
+        field: &str,
+        writer_print: &mut EventWriter<PrintEvent>,
+        writer_spawn: &mut EventWriter<SpawnEvent>,
+        commands: &mut Commands,
+    ) -> Result<Place, RuntimeError> {
+        // A vec2 is a copy, so changing its field means writing a new one back to
+        // wherever it's stored. Look up that place first if there is one.
+        let inner = match target {
+            Expr::Variable(..) | Expr::Index(..) | Expr::Field(..) => {
+                Some(self.eval_place(target, writer_print, writer_spawn, commands)?)
+            }
+            _ => None,
+        };
+        let target_val = match &inner {
+            Some(place) => self.read_place(place)?,
+            None => self.eval_expr(target, writer_print, writer_spawn, commands)?,
+        };
+        match (target_val, inner) {
+            (Value::Vec2(v), Some(inner)) => {
+                vec2_field(v, field)?;
+                Ok(Place::Vec2Field(Box::new(inner), field.to_string()))
+            }
+            (Value::Vec2(_), None) => Err(vec2_read_only()),
+            (target_val, _) => {
+                let (instance, i) = struct_field(&target_val, field)?;
+                Ok(Place::Field(instance, i))
+            }
+        }
+    }
+
+    fn read_place(&self, place: &Place) -> Result<Value, RuntimeError> {
+        match place {
+            Place::Var(name, slot) => self.get_var(name, *slot).ok_or_else(|| {
+                RuntimeError::new(
+                    ErrorKind::UndefinedVariable,
+                    format!("Variable '{}' not found!", name),
+                )
+            }),
+            Place::Index(target, index) => index_op(target, index),
+            Place::Field(instance, i) => Ok(instance.values.borrow()[*i].clone()),
+            Place::Vec2Field(inner, field) => match self.read_place(inner)? {
+                Value::Vec2(v) => vec2_field(v, field),
+                other => Err(RuntimeError::new(
+                    ErrorKind::TypeMismatch,
+                    format!("Cannot read field '{}' of {}", field, other.type_name()),
+                )),
+            },
+        }
+    }
+
+    fn write_place(&mut self, place: Place, value: Value) -> Result<(), RuntimeError> {
+        match place {
+            Place::Var(name, slot) => self.assign_var(&name, slot, value),
+            Place::Index(target, index) => assign_index(&target, &index, value),
+            Place::Field(instance, i) => {
+                let value = check_annotation(value, &instance.decl.fields[i].type_annotation)?;
+                instance.values.borrow_mut()[i] = value;
+                Ok(())
             }
-            Statement::Return(expr) => self.eval_expr(expr),
+            // Read the vector again, an earlier target of `v.x, v.y = ...` may have changed it.
+            Place::Vec2Field(inner, field) => match self.read_place(&inner)? {
+                Value::Vec2(v) => {
+                    let v = set_vec2_field(v, &field, &value)?;
+                    self.write_place(*inner, Value::Vec2(v))
+                }
+                other => Err(RuntimeError::new(
+                    ErrorKind::TypeMismatch,
+                    format!("Cannot set field '{}' of {}", field, other.type_name()),
+                )),
+            },
         }
     }
 
+    // The `Error` value a catch block receives. A rethrown error is passed on unchanged.
+    fn caught_error(&self, error: RuntimeError) -> Value {
+        if let Some(Value::Struct(instance)) = &error.thrown
+            && instance.decl.name == "Error"
+        {
+            return Value::Struct(instance.clone());
+        }
+        let (line, column) = error
+            .span
+            .map_or((0, 0), |span| span.line_col(&self.source));
+        let values = vec![
+            Value::Str(error.message),
+            Value::Str(error.kind.to_string()),
+            Value::Int(line as i64),
+            Value::Int(column as i64),
+            error.thrown.unwrap_or(Value::Unit),
+        ];
+        Value::Struct(Rc::new(StructValue {
+            decl: error_struct(),
+            values: RefCell::new(values),
+        }))
+    }
+
+    // Run the top-level statements, stopping at the first runtime error.
     fn eval_program(
         &mut self,
         stmts: &[Statement],
         writer_print: &mut EventWriter<PrintEvent>,
         writer_spawn: &mut EventWriter<SpawnEvent>,
         commands: &mut Commands,
-    ) {
+    ) -> Result<(), RuntimeError> {
         for stmt in stmts {
-            if let Some(ret_val) = self.eval_statement(stmt, writer_print, writer_spawn, commands) {
+            let ret_val = match stmt {
+                // Show what top-level calls return.
+                Statement::Expression(Expr::Call(callee, arguments, span)) => self.eval_call(
+                    callee,
+                    arguments,
+                    *span,
+                    writer_print,
+                    writer_spawn,
+                    commands,
+                )?,
+                _ => match self.eval_statement(stmt, writer_print, writer_spawn, commands)? {
+                    Flow::Normal => Value::Unit,
+                    Flow::Return(v) => v,
+                    Flow::Break(label, span) | Flow::Continue(label, span) => {
+                        return Err(stray_jump(label, span));
+                    }
+                },
+            };
+            if !matches!(ret_val, Value::Unit) {
                 writer_print.send(PrintEvent {
                     message: format!("Returned : {}", ret_val),
+                    is_error: false,
                 });
             }
         }
+        Ok(())
+    }
+}
+
+// Something an assignment can write to, with the parts of the target already evaluated.
+enum Place {
+    Var(String, Slot),
+    Index(Value, Value), // list or map, and the index or key
+    Field(Rc<StructValue>, usize),
+    Vec2Field(Box<Place>, String), // where the vector is stored, and "x" or "y"
+}
+
+// How a statement finished, so blocks, loops and calls know whether to keep going.
+enum Flow {
+    Normal,
+    Return(Value),
+    // Optional loop label, and where the jump was written for error reporting.
+    Break(Option<String>, Span),
+    Continue(Option<String>, Span),
+}
+
+// Make sure a value fits the declared type of the variable or parameter it goes into,
+// giving it back as a float if it's an int going into a float.
+fn check_annotation(value: Value, annotation: &str) -> Result<Value, RuntimeError> {
+    let expected = Type::from_annotation(annotation);
+    if let (Value::Int(n), Some(Type::Float)) = (&value, &expected) {
+        return Ok(Value::Float(*n as f64));
+    }
+    let found = Type::of_value(&value);
+    let matches = match expected {
+        Some(expected) => expected.accepts(&found),
+        // The checker has already rejected unknown names, so this is a struct or enum.
+        None => value.type_name() == annotation,
+    };
+    if matches {
+        Ok(value)
+    } else {
+        Err(RuntimeError::new(
+            ErrorKind::TypeMismatch,
+            format!("Expected {}, found {}", annotation, found),
+        ))
+    }
+}
+
+// The struct instance behind `value` and the index of `field` in it.
+fn struct_field(value: &Value, field: &str) -> Result<(Rc<StructValue>, usize), RuntimeError> {
+    match value {
+        Value::Struct(instance) => match instance.decl.field_index(field) {
+            Some(i) => Ok((instance.clone(), i)),
+            None => Err(no_field(&instance.decl.name, field)),
+        },
+        other => Err(RuntimeError::new(
+            ErrorKind::InvalidOperation,
+            format!("Cannot read field '{}' of {}", field, other.type_name()),
+        )),
+    }
+}
+
+// Vectors are values, so changing a field would only change a copy.
+fn vec2_read_only() -> RuntimeError {
+    RuntimeError::new(
+        ErrorKind::InvalidOperation,
+        "Only a vec2 stored in a variable, list, map or field can have its fields assigned",
+    )
+}
+
+fn no_variant(enum_name: &str, variant: &str) -> RuntimeError {
+    RuntimeError::new(
+        ErrorKind::InvalidOperation,
+        format!("Enum '{}' has no variant '{}'", enum_name, variant),
+    )
+}
+
+// Whether `value` fits `pattern`, pushing the values it binds onto `bound`.
+fn match_pattern(pattern: &Pattern, value: &Value, bound: &mut Vec<Value>) -> bool {
+    match pattern {
+        Pattern::Wildcard => true,
+        Pattern::Binding(_) => {
+            bound.push(value.clone());
+            true
+        }
+        Pattern::Literal(literal) => values_equal(literal, value).unwrap_or(false),
+        Pattern::Range(start, end, inclusive) => match value {
+            Value::Int(n) if *inclusive => (*start..=*end).contains(n),
+            Value::Int(n) => (*start..*end).contains(n),
+            _ => false,
+        },
+        Pattern::Variant(enum_name, variant, payload) => match value {
+            Value::Enum(value) => {
+                enum_name
+                    .as_ref()
+                    .is_none_or(|name| *name == value.decl.name)
+                    && value.variant_name() == variant
+                    && payload.len() == value.payload.len()
+                    && payload
+                        .iter()
+                        .zip(&value.payload)
+                        .all(|(p, v)| match_pattern(p, v, bound))
+            }
+            _ => false,
+        },
+    }
+}
+
+fn no_field(struct_name: &str, field: &str) -> RuntimeError {
+    RuntimeError::new(
+        ErrorKind::InvalidOperation,
+        format!("Struct '{}' has no field '{}'", struct_name, field),
+    )
+}
+
+// The value a declaration without an initializer starts with.
+fn zero_value(type_annotation: &str) -> Option<Value> {
+    match type_annotation {
+        "int" => Some(Value::Int(0)),
+        "float" => Some(Value::Float(0.0)),
+        "str" => Some(Value::Str(String::new())),
+        "bool" => Some(Value::Bool(false)),
+        "list" => Some(Value::List(Rc::default())),
+        "map" => Some(Value::Map(Rc::default())),
+        "vec2" => Some(Value::Vec2(bevy::math::DVec2::ZERO)),
+        "optional" => Some(Value::Optional(None)),
+        _ => None,
+    }
+}
+
+// Whether a break/continue aimed at `target` belongs to a loop labelled `label`.
+// An unlabelled jump always belongs to the innermost loop.
+fn targets(label: &Option<String>, target: &Option<String>) -> bool {
+    target.is_none() || target == label
+}
+
+// A break/continue that made it out of every loop.
+fn stray_jump(label: Option<String>, span: Span) -> RuntimeError {
+    let message = match label {
+        Some(label) => format!("No enclosing loop labelled '{}'", label),
+        None => "break/continue used outside of a loop".to_string(),
+    };
+    RuntimeError::new(ErrorKind::InvalidOperation, message).at(span)
+}
+
+impl Value {
+    // Name of the value's type as written in scripts, used in error messages.
+    pub fn type_name(&self) -> &str {
+        match self {
+            Value::Int(_) => "int",
+            Value::Float(_) => "float",
+            Value::Str(_) => "str",
+            Value::Bool(_) => "bool",
+            Value::Native(_) => "native function",
+            Value::Function(_) => "function",
+            Value::List(_) => "list",
+            Value::Map(_) => "map",
+            Value::Struct(instance) => &instance.decl.name,
+            Value::Enum(value) => &value.decl.name,
+            Value::Vec2(_) => "vec2",
+            Value::Unit => "nil",
+            Value::Optional(_) => "optional",
+        }
     }
 }
 
 // format display for printed value(remove the debug)
 impl std::fmt::Display for Value {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        match self {
-            Value::Int(n) => write!(f, "{}", n),
-            Value::Str(s) => write!(f, "{}", s),
-            Value::Float(n) => write!(f, "{}", n),
-            Value::Bool(b) => write!(f, "{}", b),
-            Value::Native(_) => write!(f, "[native function]"),
+        write_value(f, self, &mut Vec::new(), 0)
+    }
+}
+
+// Nesting deeper than this prints as `...`, the interpreter's stack is only so big.
+const MAX_PRINT_DEPTH: usize = 256;
+
+// Write a value nested `depth` levels inside the one being printed. `open` holds
+// the lists, maps and structs being written around it, so one that contains
+// itself prints as `[...]` instead of recursing forever.
+fn write_value(
+    f: &mut std::fmt::Formatter,
+    value: &Value,
+    open: &mut Vec<*const ()>,
+    depth: usize,
+) -> std::fmt::Result {
+    if depth > MAX_PRINT_DEPTH {
+        return write!(f, "...");
+    }
+    let ptr = match value {
+        Value::List(items) => Rc::as_ptr(items) as *const (),
+        Value::Map(entries) => Rc::as_ptr(entries) as *const (),
+        Value::Struct(instance) => Rc::as_ptr(instance) as *const (),
+        _ => return write_contents(f, value, open, depth),
+    };
+    if open.contains(&ptr) {
+        return match value {
+            Value::List(_) => write!(f, "[...]"),
+            Value::Map(_) => write!(f, "{{...}}"),
+            _ => write!(f, "{} {{...}}", value.type_name()),
+        };
+    }
+    open.push(ptr);
+    let result = write_contents(f, value, open, depth);
+    open.pop();
+    result
+}
+
+fn write_contents(
+    f: &mut std::fmt::Formatter,
+    value: &Value,
+    open: &mut Vec<*const ()>,
+    depth: usize,
+) -> std::fmt::Result {
+    match value {
+        Value::Int(n) => write!(f, "{}", n),
+        Value::Str(s) => write!(f, "{}", s),
+        // Debug keeps the ".0", so 2.0 doesn't print like the int 2.
+        Value::Float(n) => write!(f, "{:?}", n),
+        Value::Bool(b) => write!(f, "{}", b),
+        Value::Native(_) => write!(f, "[native function]"),
+        Value::Function(func) => write!(f, "[function {}]", func.decl.display_name()),
+        Value::List(items) => {
+            write!(f, "[")?;
+            for (i, item) in items.borrow().iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write_item(f, item, open, depth)?;
+            }
+            write!(f, "]")
+        }
+        Value::Map(entries) => {
+            write!(f, "{{")?;
+            for (i, (key, value)) in entries.borrow().iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write_item(f, key, open, depth)?;
+                write!(f, ": ")?;
+                write_item(f, value, open, depth)?;
+            }
+            write!(f, "}}")
+        }
+        Value::Struct(instance) => {
+            write!(f, "{} {{ ", instance.decl.name)?;
+            for (i, (field, value)) in instance
+                .decl
+                .fields
+                .iter()
+                .zip(instance.values.borrow().iter())
+                .enumerate()
+            {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{}: ", field.name)?;
+                write_item(f, value, open, depth)?;
+            }
+            write!(f, " }}")
+        }
+        Value::Vec2(v) => write!(f, "vec2({:?}, {:?})", v.x, v.y),
+        Value::Unit => write!(f, "nil"),
+        Value::Optional(None) => write!(f, "none"),
+        Value::Optional(Some(v)) => {
+            write!(f, "some(")?;
+            write_item(f, v, open, depth)?;
+            write!(f, ")")
+        }
+        Value::Enum(value) => {
+            write!(f, "{}::{}", value.decl.name, value.variant_name())?;
+            if !value.payload.is_empty() {
+                write!(f, "(")?;
+                for (i, item) in value.payload.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write_item(f, item, open, depth)?;
+                }
+                write!(f, ")")?;
+            }
+            Ok(())
         }
     }
 }
 
+// Print an item inside a list or map. Strings are quoted so `["a, b"]`
+// and `["a", "b"]` look different.
+fn write_item(
+    f: &mut std::fmt::Formatter,
+    item: &Value,
+    open: &mut Vec<*const ()>,
+    depth: usize,
+) -> std::fmt::Result {
+    match item {
+        Value::Str(s) => write!(f, "{:?}", s),
+        _ => write_value(f, item, open, depth + 1),
+    }
+}
+
 pub fn run(
     source: String,
     mut writer_print: EventWriter<PrintEvent>,
@@ -259,16 +1283,475 @@ pub fn run(
 
     // no touch 😡😡😡😡
 
-    let stmts = match interpreter_parser::program(&source) {
+    let pragmas = match read_pragmas(&source) {
+        Ok(pragmas) => pragmas,
+        Err(message) => {
+            writer_print.send(PrintEvent {
+                message,
+                is_error: true,
+            });
+            return;
+        }
+    };
+    let max_call_depth = pragmas.max_depth.unwrap_or(DEFAULT_MAX_CALL_DEPTH);
+    let stack_size = INTERPRETER_STACK_SIZE / DEFAULT_MAX_CALL_DEPTH
+        * max_call_depth.max(DEFAULT_MAX_CALL_DEPTH);
+
+    // Every script call goes through several interpreter frames, so run on a thread
+    // with a roomy stack; `max_call_depth` keeps recursion well within it. The program
+    // shares function declarations through `Rc`, so it's parsed on that thread as well.
+    let result = std::thread::scope(|scope| {
+        std::thread::Builder::new()
+            .stack_size(stack_size)
+            .spawn_scoped(scope, || {
+                execute(
+                    &source,
+                    &pragmas,
+                    &mut writer_print,
+                    &mut writer_spawn,
+                    &mut commands,
+                )
+            })
+            .map(|handle| handle.join())
+    });
+    let message = match result {
+        Ok(Ok(Ok(()))) => return,
+        Ok(Ok(Err(message))) => message,
+        Ok(Err(_)) => "runtime error: the interpreter crashed".to_string(),
+        Err(e) => format!("runtime error: could not start the interpreter: {}", e),
+    };
+    writer_print.send(PrintEvent {
+        message,
+        is_error: true,
+    });
+}
+
+// Parse, check and run a script. Parse and type errors are sent as they're found,
+// a runtime error is handed back rendered.
+fn execute(
+    source: &str,
+    pragmas: &Pragmas,
+    writer_print: &mut EventWriter<PrintEvent>,
+    writer_spawn: &mut EventWriter<SpawnEvent>,
+    commands: &mut Commands,
+) -> Result<(), String> {
+    let mut stmts = match interpreter_parser::program(source, pragmas.mode) {
         Ok(result) => result,
         Err(e) => {
             writer_print.send(PrintEvent {
-                message: format!(" parse error : {:?}", e),
+                message: render_parse_error(source, &e, pragmas.mode),
+                is_error: true,
             });
-            return;
+            return Ok(());
         }
     };
 
+    resolve_program(&mut stmts);
+    let type_errors = check_program(&stmts);
+    if !type_errors.is_empty() {
+        for e in type_errors {
+            writer_print.send(PrintEvent {
+                message: e.render(source),
+                is_error: true,
+            });
+        }
+        return Ok(());
+    }
+
     let mut interp = Interpreter::new();
-    interp.eval_program(&stmts, &mut writer_print, &mut writer_spawn, &mut commands);
+    interp.int_mode = pragmas.overflow;
+    interp.max_call_depth = pragmas.max_depth.unwrap_or(DEFAULT_MAX_CALL_DEPTH);
+    interp.source = source.to_string();
+    interp
+        .eval_program(&stmts, writer_print, writer_spawn, commands)
+        .map_err(|e| e.render(source))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::ecs::event::Events;
+    use bevy::ecs::system::RunSystemOnce;
+    use bevy::ecs::world::World;
+
+    // Run a script through parsing, resolving, checking and evaluation, and
+    // collect everything it printed, errors included.
+    fn run_script(source: &str) -> Vec<String> {
+        let mut world = World::new();
+        world.init_resource::<Events<PrintEvent>>();
+        world.init_resource::<Events<SpawnEvent>>();
+        let source = source.to_string();
+        world
+            .run_system_once(
+                move |print: EventWriter<PrintEvent>,
+                      spawn: EventWriter<SpawnEvent>,
+                      commands: Commands| {
+                    run(source.clone(), print, spawn, commands)
+                },
+            )
+            .unwrap();
+        let events = world.resource::<Events<PrintEvent>>();
+        events
+            .get_cursor()
+            .read(events)
+            .map(|e| e.message.clone())
+            .collect()
+    }
+
+    #[test]
+    fn inner_declarations_shadow_without_touching_outer() {
+        let output = run_script(
+            "var x: int = 1;
+            if (true) { var x: str = \"inner\"; print(x); }
+            fn f(x: int) { print(x); }
+            f(3);
+            print(x);",
+        );
+        assert_eq!(output, ["inner", "3", "1"]);
+    }
+
+    #[test]
+    fn functions_see_where_they_were_defined_not_the_caller() {
+        let output = run_script(
+            "var x: int = 1;
+            fn show() { print(x); }
+            fn caller() { var x: int = 2; show(); x = 3; show(); }
+            caller();
+            fn outer() {
+                var y: int = 10;
+                fn inner() { y += 1; }
+                inner();
+                print(y);
+            }
+            outer();",
+        );
+        assert_eq!(output, ["1", "1", "11"]);
+    }
+
+    #[test]
+    fn vec2_keeps_float_precision() {
+        let output = run_script(
+            "var v: vec2 = vec2(0.1, 0.2);
+            print(v.x);
+            print(v.x == 0.1);
+            print(v * 2 - vec2(0.2, 0.0));
+            print(length(vec2(3, 4)));",
+        );
+        assert_eq!(output, ["0.1", "true", "vec2(0.0, 0.4)", "5.0"]);
+    }
+
+    #[test]
+    fn operators_follow_precedence_and_associativity() {
+        let output = run_script(
+            "print(1 + 2 * 3 - 4 / 2);
+            print(10 - 3 - 2);
+            print(2 * (3 + 4) % 5);
+            print(-2 * -3);
+            print(!(1 <= 2) || 3 >= 3 && 2 != 2);
+            print(1 < 2 == true);",
+        );
+        assert_eq!(output, ["5", "5", "4", "6", "false", "true"]);
+    }
+
+    #[test]
+    fn mixed_numbers_promote_to_float() {
+        let output = run_script("print(7 / 2); print(7 / 2.0); print(1 + 2.5); print(2.0 == 2);");
+        assert_eq!(output, ["3", "3.5", "3.5", "true"]);
+    }
+
+    #[test]
+    fn logical_operators_short_circuit() {
+        let output = run_script(
+            "print(true || 1 / 0 == 0);
+            print(false && 1 / 0 == 0);
+            print(\"ab\" + \"c\" == \"abc\" && \"a\" < \"b\");",
+        );
+        assert_eq!(output, ["true", "false", "true"]);
+    }
+
+    #[test]
+    fn calls_return_values_and_recurse() {
+        let output = run_script(
+            "fn fib(n: int) -> int { if (n < 2) { return n; } return fib(n - 1) + fib(n - 2); }
+            var x: int = fib(10) + abs(-1);
+            print(x);
+            print(fib(15));",
+        );
+        assert_eq!(output, ["56", "610"]);
+    }
+
+    #[test]
+    fn recursion_stops_at_the_default_depth() {
+        let output = run_script(
+            "fn down(n: int) -> int { return down(n + 1); }
+            try { down(0); } catch (e) { print(e.message); }",
+        );
+        assert_eq!(output, ["Maximum call depth of 256 exceeded"]);
+    }
+    #[test]
+    fn max_depth_pragma_limits_recursion() {
+        let output = run_script(
+            "#!max_depth 3
+            fn f(n: int) { print(n); f(n + 1); }
+            f(0);",
+        );
+        assert_eq!(output[..3], ["0", "1", "2"]);
+        assert!(output[3].contains("Maximum call depth of 3 exceeded"));
+    }
+
+    #[test]
+    fn assignment_checks_annotation_at_runtime() {
+        let output = run_script(
+            "var x: int = 0;
+            fn id(v) { return v; }
+            x = id(\"str\");",
+        );
+        assert_eq!(output.len(), 1);
+        assert!(
+            output[0].contains("Expected int, found str"),
+            "{}",
+            output[0]
+        );
+    }
+
+    #[test]
+    fn closures_keep_their_environment() {
+        let output = run_script(
+            "fn counter() -> fn {
+                var n: int = 0;
+                return fn() -> int { n += 1; return n; };
+            }
+            var a: fn = counter();
+            var b: fn = counter();
+            a();
+            a();
+            print(a());
+            print(b());",
+        );
+        // Top-level calls report what they returned.
+        assert_eq!(output, ["Returned : 1", "Returned : 2", "3", "1"]);
+    }
+
+    #[test]
+    fn functions_are_values() {
+        let output = run_script(
+            "fn apply(f: fn, x: int) -> int { return f(x); }
+            fn double(n: int) -> int { return n * 2; }
+            var g: fn = double;
+            print(apply(g, 4));
+            print(apply(fn(n: int) -> int { return n + 1; }, 4));
+            var callbacks: list = [double, fn(n: int) -> int { return -n; }];
+            print(callbacks[1](3));",
+        );
+        assert_eq!(output, ["8", "5", "-3"]);
+    }
+
+    #[test]
+    fn for_loops_walk_ranges() {
+        let output = run_script(
+            "var n: int = 3;
+            var out: str = \"\";
+            for i in 0..n { out = out + to_str(i); }
+            for i in 1..=n { out = out + to_str(i); }
+            for i in 10..0 step 3 { out = out + \" \" + to_str(i); }
+            print(out);
+            for i in 0..10 step 0 { }",
+        );
+        assert_eq!(output[0], "012123 10 7 4 1");
+        assert!(output[1].contains("Range step should be positive, found 0"));
+    }
+
+    #[test]
+    fn for_loops_walk_collections() {
+        let output = run_script(
+            "for c in \"hi\" { print(c); }
+            for x in [4, 5] { print(x); }
+            for k in {\"b\": 1, \"a\": 2} { print(k); }",
+        );
+        assert_eq!(output, ["h", "i", "4", "5", "b", "a"]);
+    }
+
+    #[test]
+    fn match_arms_bind_payloads() {
+        let output = run_script(
+            "enum State { Idle, Chasing(int) }
+            fn describe(s: State) {
+                match s {
+                    State::Idle => print(\"idle\"),
+                    State::Chasing(target) => print(target),
+                }
+            }
+            describe(State::Chasing(7));
+            describe(State::Idle);
+            match 5 { 1..=3 => print(\"low\"), n => print(n * 2) }",
+        );
+        assert_eq!(output, ["7", "idle", "10"]);
+    }
+
+    #[test]
+    fn strings_interpolate_and_concatenate() {
+        let output = run_script(
+            "var score: int = 7;
+            print(\"score: {score}, next: {score + 1}\");
+            print(\"a\" + \"b\" + to_str(3));",
+        );
+        assert_eq!(output, ["score: 7, next: 8", "ab3"]);
+    }
+
+    #[test]
+    fn string_natives() {
+        let output = run_script(
+            "print(len(\"h\u{e9}llo\"));
+            print(substr(\"hello\", 1, 3));
+            print(split(\"a,b\", \",\"));
+            print(upper(\"ab\") + lower(\"CD\"));
+            print(contains(\"hello\", \"ell\"));
+            print(replace(\"a-b-c\", \"-\", \"+\"));
+            print(trim(\"  x  \"));
+            print(to_int(\"42\") + 1);
+            print(to_float(\"1.5\"));",
+        );
+        assert_eq!(
+            output,
+            [
+                "5",
+                "ell",
+                "[\"a\", \"b\"]",
+                "ABcd",
+                "true",
+                "a+b+c",
+                "x",
+                "43",
+                "1.5"
+            ]
+        );
+    }
+
+    #[test]
+    fn string_natives_reject_out_of_range_arguments() {
+        let output = run_script(
+            "try { substr(\"hello\", 2, 9223372036854775807); } catch (e) { print(e.kind); }
+            try { to_int(1e30); } catch (e) { print(e.message); }",
+        );
+        assert_eq!(
+            output,
+            ["index out of bounds", "Cannot convert 1e30 to int"]
+        );
+    }
+
+    #[test]
+    fn int_overflow_is_a_script_error() {
+        let output = run_script("var x: int = 9223372036854775807;\nx = x + 1;");
+        assert_eq!(
+            output,
+            [
+                "runtime error (integer overflow) at 2:5: 9223372036854775807 + 1 doesn't fit in an int\n\
+              2 | x = x + 1;\n  |     ^^^^^"
+            ]
+        );
+        let output = run_script(
+            "#!overflow wrapping\nvar x: int = 9223372036854775807;\nx += 1;\nprint(x);",
+        );
+        assert_eq!(output, ["-9223372036854775808"]);
+    }
+
+    #[test]
+    fn catch_binds_the_error() {
+        let output = run_script(
+            "var e: int = 1;
+            try { var x: int = 1 / 0; } catch (e) { print(e.kind); }
+            try { throw \"boom\"; } catch (err) { print(err.value); }
+            print(e);",
+        );
+        assert_eq!(output, ["division by zero", "boom", "1"]);
+    }
+
+    #[test]
+    fn native_errors_are_catchable_and_uncaught_throws_are_reported() {
+        let output = run_script(
+            "try { pop([]); } catch (e) { print(e.kind); print(e.line); }
+            throw \"bad\";",
+        );
+        assert_eq!(output[..2], ["index out of bounds", "1"]);
+        assert!(
+            output[2].starts_with("runtime error (thrown) at 2:13: bad\n"),
+            "{}",
+            output[2]
+        );
+    }
+
+    #[test]
+    fn global_writes_the_top_level_binding() {
+        let output = run_script(
+            "const LIMIT: int = 3;
+            var score: int = 1;
+            fn reset() { var score: int = 50; global score: int = LIMIT * 10; print(score); }
+            reset();
+            print(score);",
+        );
+        assert_eq!(output, ["50", "30"]);
+    }
+
+    #[test]
+    fn compound_and_multiple_assignment_reach_every_target() {
+        let output = run_script(
+            "struct P { x: int, y: int }
+            var p: P = P { x: 1, y: 2 };
+            var xs: list = [1, 2, 3];
+            var m: map = {\"k\": 10};
+            var i: int = 5;
+            i += 2; i *= 3; i -= 1; i /= 4; i %= 3; i++; i--; i++;
+            xs[0] += 10; xs[2]++;
+            m[\"k\"] -= 1;
+            p.x *= 5; p.y--;
+            var a: int = 1; var b: int = 2;
+            a, b = b, a;
+            xs[0], p.y = p.y, xs[0];
+            print(i); print(xs); print(m); print(p); print(a); print(b);",
+        );
+        assert_eq!(
+            output,
+            [
+                "3",
+                "[1, 2, 4]",
+                "{\"k\": 9}",
+                "P { x: 5, y: 11 }",
+                "2",
+                "1"
+            ]
+        );
+    }
+
+    #[test]
+    fn ints_widen_when_stored_as_floats() {
+        let output = run_script(
+            "struct Ball { x: float }
+            var speed: float = 5;
+            var b: Ball = Ball { x: 1 };
+            fn id(x) { return x; }
+            fn two() -> float { return 2; }
+            speed = id(7);
+            print(speed); print(b); print(two());",
+        );
+        assert_eq!(output, ["7.0", "Ball { x: 1.0 }", "2.0"]);
+    }
+
+    #[test]
+    fn deeply_nested_values_print_and_drop_without_overflowing() {
+        let output = run_script(
+            "struct Node { next: any }
+            var a: list = [];
+            var n: any = 0;
+            for i in 0..100000 { a = [a]; n = Node { next: n }; }
+            print(len(a));
+            print(a);
+            a = [];
+            n = 0;
+            print(\"done\");",
+        );
+        assert_eq!(output[0], "1");
+        let printed = format!("{}...{}", "[".repeat(257), "]".repeat(257));
+        assert_eq!(output[1], printed);
+        assert_eq!(output[2], "done");
+    }
 }
@@ -1,12 +1,164 @@
+use std::cell::RefCell;
+use std::ops::{Deref, DerefMut};
+use std::rc::Rc;
+
+use crate::int::error::{ErrorKind, RuntimeError};
 use crate::ui::resources::{PrintEvent, SpawnEvent};
 use bevy::ecs::{event::EventWriter, system::Commands};
+use bevy::math::{DVec2, Vec2};
 
 pub type NativeFn = fn(
     Vec<Value>,
     &mut EventWriter<PrintEvent>,
     &mut EventWriter<SpawnEvent>,
     &mut Commands,
-) -> Option<Value>;
+) -> Result<Value, RuntimeError>;
+
+// Byte range in the source, taken from the parser positions.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Span { start, end }
+    }
+
+    // 1-based line and column of the start of the span.
+    pub fn line_col(&self, source: &str) -> (usize, usize) {
+        let before = &source[..self.start.min(source.len())];
+        let line = before.matches('\n').count() + 1;
+        let column = before.rsplit('\n').next().map_or(0, |l| l.chars().count()) + 1;
+        (line, column)
+    }
+
+    // Number of characters to underline, only counting the first line.
+    pub fn width(&self, source: &str) -> usize {
+        source[self.start.min(source.len())..self.end.min(source.len())]
+            .lines()
+            .next()
+            .map_or(1, |l| l.chars().count())
+    }
+}
+
+// Key/value pairs of a map, kept in insertion order so iteration is predictable.
+pub type MapEntries = Rc<RefCell<MapItems>>;
+
+// The items of a list. Like the other containers below, dropping it hands the
+// items to `drop_nested` instead of recursing, so a list nested thousands deep
+// can't overflow the stack when the script lets go of it.
+#[derive(Clone, Default)]
+pub struct ListItems(pub Vec<Value>);
+
+#[derive(Clone, Default)]
+pub struct MapItems(pub Vec<(Value, Value)>);
+
+impl From<Vec<Value>> for ListItems {
+    fn from(items: Vec<Value>) -> Self {
+        ListItems(items)
+    }
+}
+
+impl FromIterator<Value> for ListItems {
+    fn from_iter<I: IntoIterator<Item = Value>>(items: I) -> Self {
+        ListItems(items.into_iter().collect())
+    }
+}
+
+impl From<Vec<(Value, Value)>> for MapItems {
+    fn from(entries: Vec<(Value, Value)>) -> Self {
+        MapItems(entries)
+    }
+}
+
+impl Deref for ListItems {
+    type Target = Vec<Value>;
+
+    fn deref(&self) -> &Vec<Value> {
+        &self.0
+    }
+}
+
+impl DerefMut for ListItems {
+    fn deref_mut(&mut self) -> &mut Vec<Value> {
+        &mut self.0
+    }
+}
+
+impl Deref for MapItems {
+    type Target = Vec<(Value, Value)>;
+
+    fn deref(&self) -> &Vec<(Value, Value)> {
+        &self.0
+    }
+}
+
+impl DerefMut for MapItems {
+    fn deref_mut(&mut self) -> &mut Vec<(Value, Value)> {
+        &mut self.0
+    }
+}
+
+impl Drop for ListItems {
+    fn drop(&mut self) {
+        drop_nested(std::mem::take(&mut self.0));
+    }
+}
+
+impl Drop for MapItems {
+    fn drop(&mut self) {
+        drop_nested(flatten(std::mem::take(&mut self.0)));
+    }
+}
+
+impl Drop for StructValue {
+    fn drop(&mut self) {
+        drop_nested(std::mem::take(self.values.get_mut()));
+    }
+}
+
+impl Drop for EnumValue {
+    fn drop(&mut self) {
+        drop_nested(std::mem::take(&mut self.payload));
+    }
+}
+
+fn flatten(entries: Vec<(Value, Value)>) -> Vec<Value> {
+    entries.into_iter().flat_map(|(k, v)| [k, v]).collect()
+}
+
+// Drop values without recursing: containers this was the last owner of are
+// emptied onto a work list, so each is dropped with nothing left inside it.
+fn drop_nested(mut pending: Vec<Value>) {
+    while let Some(value) = pending.pop() {
+        match value {
+            Value::List(items) => {
+                if let Ok(items) = Rc::try_unwrap(items) {
+                    pending.append(&mut items.into_inner().0);
+                }
+            }
+            Value::Map(entries) => {
+                if let Ok(entries) = Rc::try_unwrap(entries) {
+                    pending.extend(flatten(std::mem::take(&mut entries.into_inner().0)));
+                }
+            }
+            Value::Struct(instance) => {
+                if let Ok(mut instance) = Rc::try_unwrap(instance) {
+                    pending.append(instance.values.get_mut());
+                }
+            }
+            Value::Enum(value) => {
+                if let Ok(mut value) = Rc::try_unwrap(value) {
+                    pending.append(&mut value.payload);
+                }
+            }
+            Value::Optional(Some(inner)) => pending.push(*inner),
+            _ => {}
+        }
+    }
+}
 
 #[derive(Clone)]
 pub enum Value {
@@ -15,13 +167,88 @@ pub enum Value {
     Str(String),
     Bool(bool),
     Native(NativeFn),
+    Function(Rc<FunctionDef>),
+    List(Rc<RefCell<ListItems>>), // shared, so natives like push() change the caller's list
+    Map(MapEntries),
+    Struct(Rc<StructValue>),
+    Enum(Rc<EnumValue>),
+    Vec2(DVec2), // f64 like `float`, copied like numbers, unlike lists and structs
+    Unit,        // `nil`, what a function without a result gives back
+    Optional(Option<Box<Value>>), // `some(x)` or `none`
+}
+
+impl From<DVec2> for Value {
+    fn from(v: DVec2) -> Self {
+        Value::Vec2(v)
+    }
+}
+
+// Bevy works in f32, so vectors only narrow when they cross into the engine.
+impl From<Vec2> for Value {
+    fn from(v: Vec2) -> Self {
+        Value::Vec2(v.as_dvec2())
+    }
+}
+
+impl TryFrom<&Value> for Vec2 {
+    type Error = RuntimeError;
+
+    fn try_from(value: &Value) -> Result<Self, RuntimeError> {
+        match value {
+            Value::Vec2(v) => Ok(v.as_vec2()),
+            other => Err(RuntimeError::new(
+                ErrorKind::TypeMismatch,
+                format!("Expected vec2, found {}", other.type_name()),
+            )),
+        }
+    }
+}
+
+// Where a variable lives, filled in by the resolver before the program runs.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Slot {
+    // Looked up by name in the interpreter's globals.
+    #[default]
+    Global,
+    // Number of environments to walk up, then the index inside that one.
+    Local(usize, usize),
 }
 
 #[derive(Clone)]
 pub enum Expr {
-    Literal(Value),
-    Variable(String),
-    BinaryOp(Box<Expr>, String, Box<Expr>), // left, operator, right
+    Literal(Value, Span),
+    Variable(String, Slot, Span),
+    BinaryOp(Box<Expr>, String, Box<Expr>, Span), // left, operator, right
+    UnaryOp(String, Box<Expr>, Span),             // operator, operand
+    Call(Box<Expr>, Vec<Expr>, Span),             // callee, arguments
+    Lambda(Rc<FunctionDecl>),                     // fn(<params>) { <statements> }
+    List(Vec<Expr>, Span),                        // [<items>]
+    Map(Vec<(Expr, Expr)>, Span),                 // {<key>: <value>, ...}
+    Index(Box<Expr>, Box<Expr>, Span),            // target, index
+    Construct(String, Vec<(String, Expr)>, Span), // <struct name> { <field>: <value>, ... }
+    Field(Box<Expr>, String, Span),               // target, field name
+    Variant(String, String, Vec<Expr>, Span),     // <enum>::<variant>(<payload>)
+    Interpolated(Vec<Expr>, Span),                // "text {<expr>} text", parts in order
+}
+
+impl Expr {
+    pub fn span(&self) -> Span {
+        match self {
+            Expr::Literal(_, span)
+            | Expr::Variable(_, _, span)
+            | Expr::BinaryOp(_, _, _, span)
+            | Expr::UnaryOp(_, _, span)
+            | Expr::Call(_, _, span)
+            | Expr::List(_, span)
+            | Expr::Map(_, span)
+            | Expr::Index(_, _, span)
+            | Expr::Construct(_, _, span)
+            | Expr::Field(_, _, span)
+            | Expr::Variant(_, _, _, span)
+            | Expr::Interpolated(_, span) => *span,
+            Expr::Lambda(decl) => decl.span,
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -29,40 +256,228 @@ pub enum Statement {
     VarDecl(Variable),
     PrintExpr(Expr),
     ForLoop {
+        label: Option<String>,
         var_name: String,
-        start: i64,
-        end: i64,
+        iterable: Iterable,
         body: Vec<Statement>,
     },
     While {
+        label: Option<String>,
         condition: Expr,
         body: Vec<Statement>,
     },
-    Function {
-        name: String,
-        parameters: Vec<String>,
-        body: Vec<Statement>,
+    If {
+        condition: Expr,
+        then_body: Vec<Statement>,
+        else_body: Option<Vec<Statement>>,
     },
-    FunctionCall {
-        name: String,
-        arguments: Vec<Expr>,
+    Function(Rc<FunctionDecl>),
+    Struct(Rc<StructDecl>),
+    Enum(Rc<EnumDecl>),
+    Match {
+        subject: Expr,
+        arms: Vec<MatchArm>,
     },
+    Try {
+        body: Vec<Statement>,
+        error_name: String, // bound to the caught `Error` in the catch block
+        catch_body: Vec<Statement>,
+    },
+    Throw(Expr, Span),
+    Expression(Expr), // evaluated for its side effects, the grammar only allows calls
     Assignment {
         name: String,
+        slot: Slot,
+        expr: Expr,
+        span: Span,
+    },
+    IndexAssignment {
+        target: Expr,
+        index: Expr,
+        expr: Expr,
+        span: Span,
+    },
+    FieldAssignment {
+        target: Expr,
+        field: String,
         expr: Expr,
+        span: Span,
+    },
+    // "<target> += <expr>;" and friends, "x++;" is "x += 1;"
+    // The target is a variable, index or field expression.
+    CompoundAssignment {
+        target: Expr,
+        op: String, // the binary operator, "+" for "+="
+        expr: Expr,
+        span: Span,
+    },
+    // "a, b = b, a;" evaluates every value before assigning any of them.
+    MultiAssignment {
+        targets: Vec<Expr>,
+        values: Vec<Expr>,
     },
     Return(Expr),
+    Break(Option<String>, Span),    // optional loop label
+    Continue(Option<String>, Span), // optional loop label
+}
+
+// What a for loop walks over.
+#[derive(Clone)]
+pub enum Iterable {
+    // <start>..<end> or <start>..=<end>, counting down when start is past end.
+    Range {
+        start: Expr,
+        end: Expr,
+        inclusive: bool,
+        step: Option<Expr>, // distance between values, always positive
+    },
+    // Items of a list, keys of a map or characters of a string.
+    Collection(Expr),
 }
 
 #[derive(Clone)]
 pub struct Variable {
+    pub kind: VarKind,
     pub name: String,
-    pub _type_annotation: String, // "int", "float" dll
-    pub value: Value,
+    pub type_annotation: String,   // "int", "float" dll
+    pub initializer: Option<Expr>, // None starts from the type's zero value
+    pub span: Span,
+}
+
+// Which keyword declared a variable.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VarKind {
+    Var,
+    Const,  // can't be assigned to after its declaration
+    Global, // lives in the top-level scope wherever it's declared
 }
 
 #[derive(Clone)]
-pub struct FunctionDef {
-    pub parameters: Vec<String>,
+pub struct Parameter {
+    pub name: String,
+    pub type_annotation: Option<String>, // None accepts any value
+}
+
+// A struct type as written in the source: "struct <name> { <field>: <type>, ... }"
+pub struct StructDecl {
+    pub name: String,
+    pub fields: Vec<Field>,
+    pub span: Span,
+}
+
+impl StructDecl {
+    pub fn field_index(&self, name: &str) -> Option<usize> {
+        self.fields.iter().position(|f| f.name == name)
+    }
+}
+
+#[derive(Clone)]
+pub struct Field {
+    pub name: String,
+    pub type_annotation: String,
+}
+
+// An instance of a struct, field values in declaration order.
+pub struct StructValue {
+    pub decl: Rc<StructDecl>,
+    pub values: RefCell<Vec<Value>>,
+}
+
+// An enum type as written in the source: "enum <name> { <variant>(<types>), ... }"
+pub struct EnumDecl {
+    pub name: String,
+    pub variants: Vec<Variant>,
+    pub span: Span,
+}
+
+impl EnumDecl {
+    pub fn variant_index(&self, name: &str) -> Option<usize> {
+        self.variants.iter().position(|v| v.name == name)
+    }
+}
+
+pub struct Variant {
+    pub name: String,
+    pub payload: Vec<String>, // type annotations, empty for plain variants
+}
+
+// A value of an enum type: which variant, and its payload.
+pub struct EnumValue {
+    pub decl: Rc<EnumDecl>,
+    pub variant: usize,
+    pub payload: Vec<Value>,
+}
+
+impl EnumValue {
+    pub fn variant_name(&self) -> &str {
+        &self.decl.variants[self.variant].name
+    }
+}
+
+#[derive(Clone)]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub body: Vec<Statement>,
+    pub span: Span, // the pattern
+}
+
+#[derive(Clone)]
+pub enum Pattern {
+    Wildcard,                                      // _
+    Literal(Value),                                // 1, "a", true
+    Range(i64, i64, bool),                         // start, end, inclusive
+    Binding(String),                               // any lowercase name
+    Variant(Option<String>, String, Vec<Pattern>), // optional enum name, variant, payload
+}
+
+impl Pattern {
+    // Whether the pattern matches every value.
+    pub fn is_catch_all(&self) -> bool {
+        matches!(self, Pattern::Wildcard | Pattern::Binding(_))
+    }
+
+    // Names bound by the pattern, left to right.
+    pub fn bindings(&self, names: &mut Vec<String>) {
+        match self {
+            Pattern::Binding(name) => names.push(name.clone()),
+            Pattern::Variant(_, _, payload) => {
+                for p in payload {
+                    p.bindings(names);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+// A function as written in the source, shared by every closure made from it.
+pub struct FunctionDecl {
+    pub name: Option<String>, // None for lambdas
+    pub parameters: Vec<Parameter>,
+    pub return_type: Option<String>,
     pub body: Vec<Statement>,
+    pub span: Span,
+}
+
+impl FunctionDecl {
+    // Name to use in messages.
+    pub fn display_name(&self) -> &str {
+        self.name.as_deref().unwrap_or("<lambda>")
+    }
+}
+
+// A function value: its declaration plus the environment it was created in.
+pub struct FunctionDef {
+    pub decl: Rc<FunctionDecl>,
+    // Environment the function was defined in, None for top-level functions.
+    pub closure: Option<Rc<RefCell<Env>>>,
+}
+
+// Local variables of one block or call, in declaration order, chained to the
+// enclosing environment. Top-level variables live in the interpreter's globals instead.
+pub struct Env {
+    pub values: Vec<Value>,
+    // Type annotation of each value, None for bindings that accept anything.
+    pub annotations: Vec<Option<String>>,
+    pub parent: Option<Rc<RefCell<Env>>>,
 }
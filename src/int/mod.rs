@@ -1,4 +1,10 @@
+pub mod checker;
+pub mod diagnostic;
+pub mod error;
 pub mod interpreter;
 pub mod lexeme;
+pub mod native;
+pub mod operators;
 pub mod parser;
-pub mod native;
\ No newline at end of file
+pub mod pragma;
+pub mod resolver;
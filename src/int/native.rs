@@ -1,6 +1,12 @@
-use crate::int::lexeme::{NativeFn, Value};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::int::error::{ErrorKind, RuntimeError};
+use crate::int::lexeme::{ListItems, MapEntries, NativeFn, Value};
+use crate::int::operators::{list_index, map_position, missing_key};
 use crate::ui::resources::{PrintEvent, SpawnEvent};
 use bevy::ecs::{event::EventWriter, system::Commands};
+use bevy::math::DVec2;
 use std::collections::HashMap;
 
 #[macro_export]
@@ -12,22 +18,845 @@ macro_rules! native {
                 &mut EventWriter<PrintEvent>,
                 &mut EventWriter<SpawnEvent>,
                 &mut Commands,
-            ) -> Option<Value>
+            ) -> Result<Value, RuntimeError>
     };
 }
 
 pub fn native_spawn_ball(
-    _args: Vec<Value>,
+    args: Vec<Value>,
     _print: &mut EventWriter<PrintEvent>,
     spawn: &mut EventWriter<SpawnEvent>,
     _commands: &mut Commands,
-) -> Option<Value> {
+) -> Result<Value, RuntimeError> {
+    expect_args("spawn_ball", &args, 0)?;
     spawn.send(SpawnEvent {});
-    None
+    Ok(Value::Unit)
+}
+
+// len(list), len(map) or len(str)
+pub fn native_len(
+    args: Vec<Value>,
+    _print: &mut EventWriter<PrintEvent>,
+    _spawn: &mut EventWriter<SpawnEvent>,
+    _commands: &mut Commands,
+) -> Result<Value, RuntimeError> {
+    expect_args("len", &args, 1)?;
+    let len = match &args[0] {
+        Value::List(items) => items.borrow().len(),
+        Value::Map(entries) => entries.borrow().len(),
+        Value::Str(s) => s.chars().count(),
+        other => return Err(wrong_arg("len", "list, map or str", other)),
+    };
+    Ok(Value::Int(len as i64))
+}
+
+// push(list, value) appends to the end.
+pub fn native_push(
+    args: Vec<Value>,
+    _print: &mut EventWriter<PrintEvent>,
+    _spawn: &mut EventWriter<SpawnEvent>,
+    _commands: &mut Commands,
+) -> Result<Value, RuntimeError> {
+    expect_args("push", &args, 2)?;
+    let items = list_arg("push", &args[0])?;
+    items.borrow_mut().push(args[1].clone());
+    Ok(Value::Unit)
+}
+
+// pop(list) removes and returns the last item.
+pub fn native_pop(
+    args: Vec<Value>,
+    _print: &mut EventWriter<PrintEvent>,
+    _spawn: &mut EventWriter<SpawnEvent>,
+    _commands: &mut Commands,
+) -> Result<Value, RuntimeError> {
+    expect_args("pop", &args, 1)?;
+    let items = list_arg("pop", &args[0])?;
+    let last = items.borrow_mut().pop();
+    match last {
+        Some(v) => Ok(v),
+        None => Err(RuntimeError::new(
+            ErrorKind::IndexOutOfBounds,
+            "Cannot pop from an empty list",
+        )),
+    }
+}
+
+// insert(list, index, value) shifts later items up, index may be the length.
+pub fn native_insert(
+    args: Vec<Value>,
+    _print: &mut EventWriter<PrintEvent>,
+    _spawn: &mut EventWriter<SpawnEvent>,
+    _commands: &mut Commands,
+) -> Result<Value, RuntimeError> {
+    expect_args("insert", &args, 3)?;
+    let items = list_arg("insert", &args[0])?;
+    let len = items.borrow().len();
+    let i = list_index(&args[1], len + 1)?;
+    items.borrow_mut().insert(i, args[2].clone());
+    Ok(Value::Unit)
+}
+
+// remove(list, index) or remove(map, key) removes and returns the item.
+pub fn native_remove(
+    args: Vec<Value>,
+    _print: &mut EventWriter<PrintEvent>,
+    _spawn: &mut EventWriter<SpawnEvent>,
+    _commands: &mut Commands,
+) -> Result<Value, RuntimeError> {
+    expect_args("remove", &args, 2)?;
+    match &args[0] {
+        Value::Map(entries) => {
+            let i = map_position(&entries.borrow(), &args[1])?;
+            match i {
+                Some(i) => Ok(entries.borrow_mut().remove(i).1),
+                None => Err(missing_key(&args[1])),
+            }
+        }
+        other => {
+            let items = list_arg("remove", other)?;
+            let len = items.borrow().len();
+            let i = list_index(&args[1], len)?;
+            Ok(items.borrow_mut().remove(i))
+        }
+    }
+}
+
+// keys(map) lists the keys in insertion order.
+pub fn native_keys(
+    args: Vec<Value>,
+    _print: &mut EventWriter<PrintEvent>,
+    _spawn: &mut EventWriter<SpawnEvent>,
+    _commands: &mut Commands,
+) -> Result<Value, RuntimeError> {
+    expect_args("keys", &args, 1)?;
+    let entries = map_arg("keys", &args[0])?;
+    let keys = entries.borrow().iter().map(|(k, _)| k.clone()).collect();
+    Ok(Value::List(Rc::new(RefCell::new(keys))))
+}
+
+// values(map) lists the values in insertion order.
+pub fn native_values(
+    args: Vec<Value>,
+    _print: &mut EventWriter<PrintEvent>,
+    _spawn: &mut EventWriter<SpawnEvent>,
+    _commands: &mut Commands,
+) -> Result<Value, RuntimeError> {
+    expect_args("values", &args, 1)?;
+    let entries = map_arg("values", &args[0])?;
+    let values = entries.borrow().iter().map(|(_, v)| v.clone()).collect();
+    Ok(Value::List(Rc::new(RefCell::new(values))))
+}
+
+// has(map, key) tells whether the key is present.
+pub fn native_has(
+    args: Vec<Value>,
+    _print: &mut EventWriter<PrintEvent>,
+    _spawn: &mut EventWriter<SpawnEvent>,
+    _commands: &mut Commands,
+) -> Result<Value, RuntimeError> {
+    expect_args("has", &args, 2)?;
+    let entries = map_arg("has", &args[0])?;
+    let found = map_position(&entries.borrow(), &args[1])?.is_some();
+    Ok(Value::Bool(found))
+}
+
+// sort(list) sorts numbers or strings in place, in ascending order.
+pub fn native_sort(
+    args: Vec<Value>,
+    _print: &mut EventWriter<PrintEvent>,
+    _spawn: &mut EventWriter<SpawnEvent>,
+    _commands: &mut Commands,
+) -> Result<Value, RuntimeError> {
+    expect_args("sort", &args, 1)?;
+    let items = list_arg("sort", &args[0])?;
+    let mut items = items.borrow_mut();
+    let numbers = items
+        .iter()
+        .all(|v| matches!(v, Value::Int(_) | Value::Float(_)));
+    let strings = items.iter().all(|v| matches!(v, Value::Str(_)));
+    if !numbers && !strings {
+        return Err(RuntimeError::new(
+            ErrorKind::InvalidOperation,
+            "sort() needs a list of only numbers or only strings",
+        ));
+    }
+    items.sort_by(|a, b| match (a, b) {
+        (Value::Int(a), Value::Int(b)) => a.cmp(b),
+        (Value::Str(a), Value::Str(b)) => a.cmp(b),
+        _ => as_float(a).total_cmp(&as_float(b)),
+    });
+    Ok(Value::Unit)
+}
+
+// substr(str, start, length) takes `length` characters from `start`.
+pub fn native_substr(
+    args: Vec<Value>,
+    _print: &mut EventWriter<PrintEvent>,
+    _spawn: &mut EventWriter<SpawnEvent>,
+    _commands: &mut Commands,
+) -> Result<Value, RuntimeError> {
+    expect_args("substr", &args, 3)?;
+    let s = str_arg("substr", &args[0])?;
+    let start = int_arg("substr", &args[1])?;
+    let length = int_arg("substr", &args[2])?;
+    let count = s.chars().count() as i64;
+    if start < 0 || length < 0 || start > count || length > count - start {
+        return Err(RuntimeError::new(
+            ErrorKind::IndexOutOfBounds,
+            format!(
+                "Cannot take {} character(s) from {} in a string of length {}",
+                length, start, count
+            ),
+        ));
+    }
+    let sub = s
+        .chars()
+        .skip(start as usize)
+        .take(length as usize)
+        .collect();
+    Ok(Value::Str(sub))
+}
+
+// split(str, separator) gives a list of the pieces.
+pub fn native_split(
+    args: Vec<Value>,
+    _print: &mut EventWriter<PrintEvent>,
+    _spawn: &mut EventWriter<SpawnEvent>,
+    _commands: &mut Commands,
+) -> Result<Value, RuntimeError> {
+    expect_args("split", &args, 2)?;
+    let s = str_arg("split", &args[0])?;
+    let separator = str_arg("split", &args[1])?;
+    if separator.is_empty() {
+        return Err(RuntimeError::new(
+            ErrorKind::InvalidOperation,
+            "split() needs a non-empty separator",
+        ));
+    }
+    let pieces = s
+        .split(separator)
+        .map(|p| Value::Str(p.to_string()))
+        .collect();
+    Ok(Value::List(Rc::new(RefCell::new(pieces))))
+}
+
+pub fn native_upper(
+    args: Vec<Value>,
+    _print: &mut EventWriter<PrintEvent>,
+    _spawn: &mut EventWriter<SpawnEvent>,
+    _commands: &mut Commands,
+) -> Result<Value, RuntimeError> {
+    expect_args("upper", &args, 1)?;
+    Ok(Value::Str(str_arg("upper", &args[0])?.to_uppercase()))
+}
+
+pub fn native_lower(
+    args: Vec<Value>,
+    _print: &mut EventWriter<PrintEvent>,
+    _spawn: &mut EventWriter<SpawnEvent>,
+    _commands: &mut Commands,
+) -> Result<Value, RuntimeError> {
+    expect_args("lower", &args, 1)?;
+    Ok(Value::Str(str_arg("lower", &args[0])?.to_lowercase()))
+}
+
+// contains(str, part)
+pub fn native_contains(
+    args: Vec<Value>,
+    _print: &mut EventWriter<PrintEvent>,
+    _spawn: &mut EventWriter<SpawnEvent>,
+    _commands: &mut Commands,
+) -> Result<Value, RuntimeError> {
+    expect_args("contains", &args, 2)?;
+    let s = str_arg("contains", &args[0])?;
+    let part = str_arg("contains", &args[1])?;
+    Ok(Value::Bool(s.contains(part)))
+}
+
+// replace(str, from, to) replaces every occurrence.
+pub fn native_replace(
+    args: Vec<Value>,
+    _print: &mut EventWriter<PrintEvent>,
+    _spawn: &mut EventWriter<SpawnEvent>,
+    _commands: &mut Commands,
+) -> Result<Value, RuntimeError> {
+    expect_args("replace", &args, 3)?;
+    let s = str_arg("replace", &args[0])?;
+    let from = str_arg("replace", &args[1])?;
+    let to = str_arg("replace", &args[2])?;
+    if from.is_empty() {
+        return Err(RuntimeError::new(
+            ErrorKind::InvalidOperation,
+            "replace() needs a non-empty string to replace",
+        ));
+    }
+    Ok(Value::Str(s.replace(from, to)))
+}
+
+pub fn native_trim(
+    args: Vec<Value>,
+    _print: &mut EventWriter<PrintEvent>,
+    _spawn: &mut EventWriter<SpawnEvent>,
+    _commands: &mut Commands,
+) -> Result<Value, RuntimeError> {
+    expect_args("trim", &args, 1)?;
+    Ok(Value::Str(str_arg("trim", &args[0])?.trim().to_string()))
+}
+
+// to_int(value) parses strings and drops the fraction of floats.
+pub fn native_to_int(
+    args: Vec<Value>,
+    _print: &mut EventWriter<PrintEvent>,
+    _spawn: &mut EventWriter<SpawnEvent>,
+    _commands: &mut Commands,
+) -> Result<Value, RuntimeError> {
+    expect_args("to_int", &args, 1)?;
+    let n = match &args[0] {
+        Value::Int(n) => *n,
+        // Drops the fraction, like floor() and round() it rejects floats an int can't hold.
+        Value::Float(_) => return to_whole("to_int", &args[0], f64::trunc),
+        Value::Bool(b) => *b as i64,
+        Value::Str(s) => s
+            .trim()
+            .parse()
+            .map_err(|_| cannot_convert(&args[0], "int"))?,
+        other => return Err(cannot_convert(other, "int")),
+    };
+    Ok(Value::Int(n))
+}
+
+// to_float(value) parses strings and widens ints.
+pub fn native_to_float(
+    args: Vec<Value>,
+    _print: &mut EventWriter<PrintEvent>,
+    _spawn: &mut EventWriter<SpawnEvent>,
+    _commands: &mut Commands,
+) -> Result<Value, RuntimeError> {
+    expect_args("to_float", &args, 1)?;
+    let n = match &args[0] {
+        Value::Int(n) => *n as f64,
+        Value::Float(n) => *n,
+        Value::Str(s) => s
+            .trim()
+            .parse()
+            .map_err(|_| cannot_convert(&args[0], "float"))?,
+        other => return Err(cannot_convert(other, "float")),
+    };
+    Ok(Value::Float(n))
+}
+
+// to_str(value) gives the text print() would show.
+pub fn native_to_str(
+    args: Vec<Value>,
+    _print: &mut EventWriter<PrintEvent>,
+    _spawn: &mut EventWriter<SpawnEvent>,
+    _commands: &mut Commands,
+) -> Result<Value, RuntimeError> {
+    expect_args("to_str", &args, 1)?;
+    Ok(Value::Str(args[0].to_string()))
+}
+
+// abs(number) keeps ints as ints.
+pub fn native_abs(
+    args: Vec<Value>,
+    _print: &mut EventWriter<PrintEvent>,
+    _spawn: &mut EventWriter<SpawnEvent>,
+    _commands: &mut Commands,
+) -> Result<Value, RuntimeError> {
+    expect_args("abs", &args, 1)?;
+    match &args[0] {
+        Value::Int(n) => n
+            .checked_abs()
+            .map(Value::Int)
+            .ok_or_else(|| overflow("abs", *n)),
+        other => Ok(Value::Float(num_arg("abs", other)?.abs())),
+    }
+}
+
+// min(a, b) and max(a, b) give an int when both are ints, a float otherwise.
+pub fn native_min(
+    args: Vec<Value>,
+    _print: &mut EventWriter<PrintEvent>,
+    _spawn: &mut EventWriter<SpawnEvent>,
+    _commands: &mut Commands,
+) -> Result<Value, RuntimeError> {
+    expect_args("min", &args, 2)?;
+    match (&args[0], &args[1]) {
+        (Value::Int(a), Value::Int(b)) => Ok(Value::Int(*a.min(b))),
+        (a, b) => Ok(Value::Float(num_arg("min", a)?.min(num_arg("min", b)?))),
+    }
+}
+
+pub fn native_max(
+    args: Vec<Value>,
+    _print: &mut EventWriter<PrintEvent>,
+    _spawn: &mut EventWriter<SpawnEvent>,
+    _commands: &mut Commands,
+) -> Result<Value, RuntimeError> {
+    expect_args("max", &args, 2)?;
+    match (&args[0], &args[1]) {
+        (Value::Int(a), Value::Int(b)) => Ok(Value::Int(*a.max(b))),
+        (a, b) => Ok(Value::Float(num_arg("max", a)?.max(num_arg("max", b)?))),
+    }
+}
+
+// clamp(value, low, high) keeps ints as ints, like min and max.
+pub fn native_clamp(
+    args: Vec<Value>,
+    _print: &mut EventWriter<PrintEvent>,
+    _spawn: &mut EventWriter<SpawnEvent>,
+    _commands: &mut Commands,
+) -> Result<Value, RuntimeError> {
+    expect_args("clamp", &args, 3)?;
+    let bad_bounds = || {
+        RuntimeError::new(
+            ErrorKind::InvalidOperation,
+            format!(
+                "clamp() low bound {} is above high bound {}",
+                args[1], args[2]
+            ),
+        )
+    };
+    match (&args[0], &args[1], &args[2]) {
+        (Value::Int(v), Value::Int(lo), Value::Int(hi)) if lo <= hi => {
+            Ok(Value::Int(*v.clamp(lo, hi)))
+        }
+        (Value::Int(_), Value::Int(_), Value::Int(_)) => Err(bad_bounds()),
+        (v, lo, hi) => {
+            let (v, lo, hi) = (
+                num_arg("clamp", v)?,
+                num_arg("clamp", lo)?,
+                num_arg("clamp", hi)?,
+            );
+            // f64::clamp panics on bad bounds, including NaN ones.
+            if lo > hi || lo.is_nan() || hi.is_nan() {
+                return Err(bad_bounds());
+            }
+            Ok(Value::Float(v.clamp(lo, hi)))
+        }
+    }
+}
+
+// pow(base, exponent) stays an int for an int base and a non-negative int exponent.
+pub fn native_pow(
+    args: Vec<Value>,
+    _print: &mut EventWriter<PrintEvent>,
+    _spawn: &mut EventWriter<SpawnEvent>,
+    _commands: &mut Commands,
+) -> Result<Value, RuntimeError> {
+    expect_args("pow", &args, 2)?;
+    match (&args[0], &args[1]) {
+        (Value::Int(base), Value::Int(exp)) if *exp >= 0 => u32::try_from(*exp)
+            .ok()
+            .and_then(|exp| base.checked_pow(exp))
+            .map(Value::Int)
+            .ok_or_else(|| overflow("pow", *base)),
+        (base, exp) => Ok(Value::Float(
+            num_arg("pow", base)?.powf(num_arg("pow", exp)?),
+        )),
+    }
+}
+
+pub fn native_sqrt(
+    args: Vec<Value>,
+    _print: &mut EventWriter<PrintEvent>,
+    _spawn: &mut EventWriter<SpawnEvent>,
+    _commands: &mut Commands,
+) -> Result<Value, RuntimeError> {
+    expect_args("sqrt", &args, 1)?;
+    Ok(Value::Float(num_arg("sqrt", &args[0])?.sqrt()))
+}
+
+// sin(radians)
+pub fn native_sin(
+    args: Vec<Value>,
+    _print: &mut EventWriter<PrintEvent>,
+    _spawn: &mut EventWriter<SpawnEvent>,
+    _commands: &mut Commands,
+) -> Result<Value, RuntimeError> {
+    expect_args("sin", &args, 1)?;
+    Ok(Value::Float(num_arg("sin", &args[0])?.sin()))
+}
+
+// cos(radians)
+pub fn native_cos(
+    args: Vec<Value>,
+    _print: &mut EventWriter<PrintEvent>,
+    _spawn: &mut EventWriter<SpawnEvent>,
+    _commands: &mut Commands,
+) -> Result<Value, RuntimeError> {
+    expect_args("cos", &args, 1)?;
+    Ok(Value::Float(num_arg("cos", &args[0])?.cos()))
+}
+
+// atan2(y, x) gives the angle of the point (x, y) in radians.
+pub fn native_atan2(
+    args: Vec<Value>,
+    _print: &mut EventWriter<PrintEvent>,
+    _spawn: &mut EventWriter<SpawnEvent>,
+    _commands: &mut Commands,
+) -> Result<Value, RuntimeError> {
+    expect_args("atan2", &args, 2)?;
+    let y = num_arg("atan2", &args[0])?;
+    let x = num_arg("atan2", &args[1])?;
+    Ok(Value::Float(y.atan2(x)))
+}
+
+// floor, ceil and round give ints, ready to use as indexes.
+pub fn native_floor(
+    args: Vec<Value>,
+    _print: &mut EventWriter<PrintEvent>,
+    _spawn: &mut EventWriter<SpawnEvent>,
+    _commands: &mut Commands,
+) -> Result<Value, RuntimeError> {
+    expect_args("floor", &args, 1)?;
+    to_whole("floor", &args[0], f64::floor)
+}
+
+pub fn native_ceil(
+    args: Vec<Value>,
+    _print: &mut EventWriter<PrintEvent>,
+    _spawn: &mut EventWriter<SpawnEvent>,
+    _commands: &mut Commands,
+) -> Result<Value, RuntimeError> {
+    expect_args("ceil", &args, 1)?;
+    to_whole("ceil", &args[0], f64::ceil)
+}
+
+pub fn native_round(
+    args: Vec<Value>,
+    _print: &mut EventWriter<PrintEvent>,
+    _spawn: &mut EventWriter<SpawnEvent>,
+    _commands: &mut Commands,
+) -> Result<Value, RuntimeError> {
+    expect_args("round", &args, 1)?;
+    to_whole("round", &args[0], f64::round)
+}
+
+// lerp(a, b, t) goes from a at t = 0 to b at t = 1.
+pub fn native_lerp(
+    args: Vec<Value>,
+    _print: &mut EventWriter<PrintEvent>,
+    _spawn: &mut EventWriter<SpawnEvent>,
+    _commands: &mut Commands,
+) -> Result<Value, RuntimeError> {
+    expect_args("lerp", &args, 3)?;
+    let a = num_arg("lerp", &args[0])?;
+    let b = num_arg("lerp", &args[1])?;
+    let t = num_arg("lerp", &args[2])?;
+    Ok(Value::Float(a + (b - a) * t))
+}
+
+// vec2(x, y)
+pub fn native_vec2(
+    args: Vec<Value>,
+    _print: &mut EventWriter<PrintEvent>,
+    _spawn: &mut EventWriter<SpawnEvent>,
+    _commands: &mut Commands,
+) -> Result<Value, RuntimeError> {
+    expect_args("vec2", &args, 2)?;
+    let x = num_arg("vec2", &args[0])?;
+    let y = num_arg("vec2", &args[1])?;
+    Ok(DVec2::new(x, y).into())
+}
+
+pub fn native_length(
+    args: Vec<Value>,
+    _print: &mut EventWriter<PrintEvent>,
+    _spawn: &mut EventWriter<SpawnEvent>,
+    _commands: &mut Commands,
+) -> Result<Value, RuntimeError> {
+    expect_args("length", &args, 1)?;
+    let v = vec2_arg("length", &args[0])?;
+    Ok(Value::Float(v.length()))
+}
+
+// normalize(v) gives a vector of length 1, or zero for the zero vector.
+pub fn native_normalize(
+    args: Vec<Value>,
+    _print: &mut EventWriter<PrintEvent>,
+    _spawn: &mut EventWriter<SpawnEvent>,
+    _commands: &mut Commands,
+) -> Result<Value, RuntimeError> {
+    expect_args("normalize", &args, 1)?;
+    let v = vec2_arg("normalize", &args[0])?;
+    Ok(v.normalize_or_zero().into())
+}
+
+pub fn native_dot(
+    args: Vec<Value>,
+    _print: &mut EventWriter<PrintEvent>,
+    _spawn: &mut EventWriter<SpawnEvent>,
+    _commands: &mut Commands,
+) -> Result<Value, RuntimeError> {
+    expect_args("dot", &args, 2)?;
+    let a = vec2_arg("dot", &args[0])?;
+    let b = vec2_arg("dot", &args[1])?;
+    Ok(Value::Float(a.dot(b)))
+}
+
+pub fn native_distance(
+    args: Vec<Value>,
+    _print: &mut EventWriter<PrintEvent>,
+    _spawn: &mut EventWriter<SpawnEvent>,
+    _commands: &mut Commands,
+) -> Result<Value, RuntimeError> {
+    expect_args("distance", &args, 2)?;
+    let a = vec2_arg("distance", &args[0])?;
+    let b = vec2_arg("distance", &args[1])?;
+    Ok(Value::Float(a.distance(b)))
+}
+
+// some(value) wraps a value that might have been missing.
+pub fn native_some(
+    args: Vec<Value>,
+    _print: &mut EventWriter<PrintEvent>,
+    _spawn: &mut EventWriter<SpawnEvent>,
+    _commands: &mut Commands,
+) -> Result<Value, RuntimeError> {
+    expect_args("some", &args, 1)?;
+    let value = args.into_iter().next().unwrap_or(Value::Unit);
+    Ok(Value::Optional(Some(Box::new(value))))
+}
+
+// is_none(optional) tells whether there is no value.
+pub fn native_is_none(
+    args: Vec<Value>,
+    _print: &mut EventWriter<PrintEvent>,
+    _spawn: &mut EventWriter<SpawnEvent>,
+    _commands: &mut Commands,
+) -> Result<Value, RuntimeError> {
+    expect_args("is_none", &args, 1)?;
+    let value = optional_arg("is_none", &args[0])?;
+    Ok(Value::Bool(value.is_none()))
+}
+
+// is_some(optional) tells whether there is a value.
+pub fn native_is_some(
+    args: Vec<Value>,
+    _print: &mut EventWriter<PrintEvent>,
+    _spawn: &mut EventWriter<SpawnEvent>,
+    _commands: &mut Commands,
+) -> Result<Value, RuntimeError> {
+    expect_args("is_some", &args, 1)?;
+    let value = optional_arg("is_some", &args[0])?;
+    Ok(Value::Bool(value.is_some()))
+}
+
+// unwrap(optional) gives the wrapped value, and fails on none.
+pub fn native_unwrap(
+    args: Vec<Value>,
+    _print: &mut EventWriter<PrintEvent>,
+    _spawn: &mut EventWriter<SpawnEvent>,
+    _commands: &mut Commands,
+) -> Result<Value, RuntimeError> {
+    expect_args("unwrap", &args, 1)?;
+    match optional_arg("unwrap", &args[0])? {
+        Some(v) => Ok(v.clone()),
+        None => Err(RuntimeError::new(
+            ErrorKind::InvalidOperation,
+            "Cannot unwrap none",
+        )),
+    }
+}
+
+// get(map, key) or get(list, index) is like indexing, but gives none when the
+// key or index is missing instead of failing.
+pub fn native_get(
+    args: Vec<Value>,
+    _print: &mut EventWriter<PrintEvent>,
+    _spawn: &mut EventWriter<SpawnEvent>,
+    _commands: &mut Commands,
+) -> Result<Value, RuntimeError> {
+    expect_args("get", &args, 2)?;
+    let found = match &args[0] {
+        Value::Map(entries) => {
+            let entries = entries.borrow();
+            map_position(&entries, &args[1])?.map(|i| entries[i].1.clone())
+        }
+        other => {
+            let items = list_arg("get", other)?;
+            let i = int_arg("get", &args[1])?;
+            let items = items.borrow();
+            usize::try_from(i).ok().and_then(|i| items.get(i)).cloned()
+        }
+    };
+    Ok(Value::Optional(found.map(Box::new)))
+}
+
+fn to_whole(name: &str, value: &Value, op: fn(f64) -> f64) -> Result<Value, RuntimeError> {
+    if let Value::Int(n) = value {
+        return Ok(Value::Int(*n));
+    }
+    let n = op(num_arg(name, value)?);
+    // Casting saturates silently, so reject anything an int can't hold.
+    if n.is_finite() && n >= i64::MIN as f64 && n < i64::MAX as f64 {
+        Ok(Value::Int(n as i64))
+    } else {
+        Err(cannot_convert(value, "int"))
+    }
+}
+
+fn overflow(name: &str, n: i64) -> RuntimeError {
+    RuntimeError::new(
+        ErrorKind::Overflow,
+        format!("{}() overflowed the int range with {}", name, n),
+    )
+}
+
+fn cannot_convert(value: &Value, target: &str) -> RuntimeError {
+    let shown = match value {
+        Value::Str(s) => format!("{:?}", s),
+        other => other.to_string(),
+    };
+    RuntimeError::new(
+        ErrorKind::InvalidOperation,
+        format!("Cannot convert {} to {}", shown, target),
+    )
+}
+
+fn as_float(value: &Value) -> f64 {
+    match value {
+        Value::Int(n) => *n as f64,
+        Value::Float(n) => *n,
+        _ => f64::NAN,
+    }
+}
+
+fn expect_args(name: &str, args: &[Value], count: usize) -> Result<(), RuntimeError> {
+    if args.len() == count {
+        return Ok(());
+    }
+    Err(RuntimeError::new(
+        ErrorKind::ArityMismatch,
+        format!(
+            "Function '{}' expects {} argument(s), got {}",
+            name,
+            count,
+            args.len()
+        ),
+    ))
+}
+
+fn list_arg(name: &str, value: &Value) -> Result<Rc<RefCell<ListItems>>, RuntimeError> {
+    match value {
+        Value::List(items) => Ok(items.clone()),
+        other => Err(wrong_arg(name, "list", other)),
+    }
+}
+
+fn str_arg<'a>(name: &str, value: &'a Value) -> Result<&'a str, RuntimeError> {
+    match value {
+        Value::Str(s) => Ok(s),
+        other => Err(wrong_arg(name, "str", other)),
+    }
+}
+
+// A number argument, ints are widened to floats.
+fn num_arg(name: &str, value: &Value) -> Result<f64, RuntimeError> {
+    match value {
+        Value::Int(n) => Ok(*n as f64),
+        Value::Float(n) => Ok(*n),
+        other => Err(wrong_arg(name, "number", other)),
+    }
+}
+
+// A vector argument, kept in f64 for math. Natives that hand it to Bevy convert
+// with `Vec2::try_from`.
+fn vec2_arg(name: &str, value: &Value) -> Result<DVec2, RuntimeError> {
+    match value {
+        Value::Vec2(v) => Ok(*v),
+        other => Err(wrong_arg(name, "vec2", other)),
+    }
+}
+
+fn int_arg(name: &str, value: &Value) -> Result<i64, RuntimeError> {
+    match value {
+        Value::Int(n) => Ok(*n),
+        other => Err(wrong_arg(name, "int", other)),
+    }
+}
+
+fn optional_arg<'a>(name: &str, value: &'a Value) -> Result<Option<&'a Value>, RuntimeError> {
+    match value {
+        Value::Optional(v) => Ok(v.as_deref()),
+        other => Err(wrong_arg(name, "optional", other)),
+    }
+}
+
+fn map_arg(name: &str, value: &Value) -> Result<MapEntries, RuntimeError> {
+    match value {
+        Value::Map(entries) => Ok(entries.clone()),
+        other => Err(wrong_arg(name, "map", other)),
+    }
+}
+
+fn wrong_arg(name: &str, expected: &str, found: &Value) -> RuntimeError {
+    RuntimeError::new(
+        ErrorKind::TypeMismatch,
+        format!(
+            "Function '{}' expects a {}, found {}",
+            name,
+            expected,
+            found.type_name()
+        ),
+    )
 }
 
 pub fn build_native_fn_table() -> HashMap<String, NativeFn> {
     let mut table = HashMap::new();
     table.insert("spawn_ball".to_string(), native!(native_spawn_ball));
+    table.insert("len".to_string(), native!(native_len));
+    table.insert("push".to_string(), native!(native_push));
+    table.insert("pop".to_string(), native!(native_pop));
+    table.insert("insert".to_string(), native!(native_insert));
+    table.insert("remove".to_string(), native!(native_remove));
+    table.insert("sort".to_string(), native!(native_sort));
+    table.insert("keys".to_string(), native!(native_keys));
+    table.insert("values".to_string(), native!(native_values));
+    table.insert("has".to_string(), native!(native_has));
+    table.insert("substr".to_string(), native!(native_substr));
+    table.insert("split".to_string(), native!(native_split));
+    table.insert("upper".to_string(), native!(native_upper));
+    table.insert("lower".to_string(), native!(native_lower));
+    table.insert("contains".to_string(), native!(native_contains));
+    table.insert("replace".to_string(), native!(native_replace));
+    table.insert("trim".to_string(), native!(native_trim));
+    table.insert("to_int".to_string(), native!(native_to_int));
+    table.insert("to_float".to_string(), native!(native_to_float));
+    table.insert("to_str".to_string(), native!(native_to_str));
+    table.insert("abs".to_string(), native!(native_abs));
+    table.insert("min".to_string(), native!(native_min));
+    table.insert("max".to_string(), native!(native_max));
+    table.insert("clamp".to_string(), native!(native_clamp));
+    table.insert("pow".to_string(), native!(native_pow));
+    table.insert("sqrt".to_string(), native!(native_sqrt));
+    table.insert("sin".to_string(), native!(native_sin));
+    table.insert("cos".to_string(), native!(native_cos));
+    table.insert("atan2".to_string(), native!(native_atan2));
+    table.insert("floor".to_string(), native!(native_floor));
+    table.insert("ceil".to_string(), native!(native_ceil));
+    table.insert("round".to_string(), native!(native_round));
+    table.insert("lerp".to_string(), native!(native_lerp));
+    table.insert("vec2".to_string(), native!(native_vec2));
+    table.insert("length".to_string(), native!(native_length));
+    table.insert("normalize".to_string(), native!(native_normalize));
+    table.insert("dot".to_string(), native!(native_dot));
+    table.insert("distance".to_string(), native!(native_distance));
+    table.insert("some".to_string(), native!(native_some));
+    table.insert("is_none".to_string(), native!(native_is_none));
+    table.insert("is_some".to_string(), native!(native_is_some));
+    table.insert("unwrap".to_string(), native!(native_unwrap));
+    table.insert("get".to_string(), native!(native_get));
+    table
+}
+
+// Named values every script can read, next to the native functions.
+pub fn build_native_const_table() -> HashMap<String, Value> {
+    let mut table = HashMap::new();
+    table.insert("PI".to_string(), Value::Float(std::f64::consts::PI));
+    table.insert("none".to_string(), Value::Optional(None));
+    table.insert("nil".to_string(), Value::Unit);
     table
 }
@@ -0,0 +1,454 @@
+use std::cmp::Ordering;
+use std::rc::Rc;
+
+use bevy::math::DVec2;
+
+use crate::int::error::{ErrorKind, RuntimeError};
+use crate::int::lexeme::Value;
+
+// What integer arithmetic does when the result doesn't fit in 64 bits.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum IntMode {
+    // Stop the script with an overflow error.
+    #[default]
+    Checked,
+    // Wrap around like the hardware does, e.g. for retro-style counters.
+    Wrapping,
+    // Stick at the largest or smallest int.
+    Saturating,
+}
+
+// Apply a binary operator to two evaluated operands.
+// Mixing int and float promotes the int, so `1 + 2.5` is `3.5`. `+` joins strings.
+pub fn binary_op(
+    op: &str,
+    left: Value,
+    right: Value,
+    mode: IntMode,
+) -> Result<Value, RuntimeError> {
+    match (left, right) {
+        // `none ?? x` is `x`. The interpreter already handled `some(v) ?? x`.
+        (Value::Optional(None), r) if op == "??" => Ok(r),
+        (Value::Optional(Some(v)), _) if op == "??" => Ok(*v),
+        (Value::Int(l), Value::Int(r)) => int_op(op, l, r, mode),
+        (Value::Float(l), Value::Float(r)) => float_op(op, l, r),
+        (Value::Int(l), Value::Float(r)) => float_op(op, l as f64, r),
+        (Value::Float(l), Value::Int(r)) => float_op(op, l, r as f64),
+        (Value::Str(l), Value::Str(r)) if op == "+" => Ok(Value::Str(l + &r)),
+        (Value::Str(l), Value::Str(r)) => match compare(op, l.cmp(&r)) {
+            Some(b) => Ok(Value::Bool(b)),
+            None => Err(unsupported(op, "str", "str")),
+        },
+        (Value::Vec2(l), Value::Vec2(r)) => match op {
+            "+" => Ok(Value::Vec2(l + r)),
+            "-" => Ok(Value::Vec2(l - r)),
+            "*" => Ok(Value::Vec2(l * r)),
+            "==" => Ok(Value::Bool(l == r)),
+            "!=" => Ok(Value::Bool(l != r)),
+            _ => Err(unsupported(op, "vec2", "vec2")),
+        },
+        // Scaling by a number, on either side for `*`.
+        (Value::Vec2(v), n @ (Value::Int(_) | Value::Float(_))) if op == "*" || op == "/" => {
+            let n = as_f64(&n);
+            Ok(Value::Vec2(if op == "*" { v * n } else { v / n }))
+        }
+        (n @ (Value::Int(_) | Value::Float(_)), Value::Vec2(v)) if op == "*" => {
+            Ok(Value::Vec2(as_f64(&n) * v))
+        }
+        (Value::Bool(l), Value::Bool(r)) => match op {
+            "==" => Ok(Value::Bool(l == r)),
+            "!=" => Ok(Value::Bool(l != r)),
+            "&&" => Ok(Value::Bool(l && r)),
+            "||" => Ok(Value::Bool(l || r)),
+            _ => Err(unsupported(op, "bool", "bool")),
+        },
+        // Values holding other values compare what they hold.
+        (l @ Value::List(_), r @ Value::List(_))
+        | (l @ Value::Map(_), r @ Value::Map(_))
+        | (l @ Value::Struct(_), r @ Value::Struct(_))
+        | (l @ Value::Enum(_), r @ Value::Enum(_))
+        | (l @ Value::Optional(_), r @ Value::Optional(_))
+            if op == "==" || op == "!=" =>
+        {
+            let equal = equal_at(&l, &r, 0)?;
+            Ok(Value::Bool(equal == (op == "==")))
+        }
+        (Value::Unit, Value::Unit) if op == "==" || op == "!=" => Ok(Value::Bool(op == "==")),
+        // Values of unrelated types are never equal.
+        (_, _) if op == "==" => Ok(Value::Bool(false)),
+        (_, _) if op == "!=" => Ok(Value::Bool(true)),
+        (l, r) => Err(unsupported(op, l.type_name(), r.type_name())),
+    }
+}
+
+// Whether two values are `==`.
+pub fn values_equal(a: &Value, b: &Value) -> Result<bool, RuntimeError> {
+    equal_at(a, b, 0)
+}
+
+// How deep `==` follows nested values. Two lists that contain themselves would
+// otherwise be compared forever and overflow the stack.
+const MAX_COMPARE_DEPTH: usize = 256;
+
+// `==` for values nested `depth` levels inside the ones being compared.
+fn equal_at(a: &Value, b: &Value, depth: usize) -> Result<bool, RuntimeError> {
+    if depth > MAX_COMPARE_DEPTH {
+        return Err(RuntimeError::new(
+            ErrorKind::InvalidOperation,
+            format!(
+                "Cannot compare values nested more than {} levels deep, does a list contain itself?",
+                MAX_COMPARE_DEPTH
+            ),
+        ));
+    }
+    let depth = depth + 1;
+    match (a, b) {
+        (Value::List(l), Value::List(r)) => {
+            Ok(Rc::ptr_eq(l, r) || lists_equal(&l.borrow(), &r.borrow(), depth)?)
+        }
+        (Value::Map(l), Value::Map(r)) => {
+            Ok(Rc::ptr_eq(l, r) || maps_equal(&l.borrow(), &r.borrow(), depth)?)
+        }
+        (Value::Struct(l), Value::Struct(r)) => Ok(Rc::ptr_eq(l, r)
+            || (l.decl.name == r.decl.name
+                && lists_equal(&l.values.borrow(), &r.values.borrow(), depth)?)),
+        (Value::Enum(l), Value::Enum(r)) => Ok(l.decl.name == r.decl.name
+            && l.variant == r.variant
+            && lists_equal(&l.payload, &r.payload, depth)?),
+        (Value::Optional(l), Value::Optional(r)) => match (l, r) {
+            (Some(l), Some(r)) => equal_at(l, r, depth),
+            (l, r) => Ok(l.is_none() && r.is_none()),
+        },
+        _ => {
+            let equal = binary_op("==", a.clone(), b.clone(), IntMode::Checked)?;
+            Ok(matches!(equal, Value::Bool(true)))
+        }
+    }
+}
+
+// Lists are equal when they hold equal items in the same order.
+fn lists_equal(l: &[Value], r: &[Value], depth: usize) -> Result<bool, RuntimeError> {
+    if l.len() != r.len() {
+        return Ok(false);
+    }
+    for (a, b) in l.iter().zip(r) {
+        if !equal_at(a, b, depth)? {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+// Maps are equal when they hold the same keys with equal values, in any order.
+fn maps_equal(
+    l: &[(Value, Value)],
+    r: &[(Value, Value)],
+    depth: usize,
+) -> Result<bool, RuntimeError> {
+    if l.len() != r.len() {
+        return Ok(false);
+    }
+    for (key, a) in l {
+        let Some(i) = map_position(r, key)? else {
+            return Ok(false);
+        };
+        if !equal_at(a, &r[i].1, depth)? {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+// Read `target[index]`.
+pub fn index_op(target: &Value, index: &Value) -> Result<Value, RuntimeError> {
+    match target {
+        Value::List(items) => {
+            let items = items.borrow();
+            let i = list_index(index, items.len())?;
+            Ok(items[i].clone())
+        }
+        Value::Map(entries) => {
+            let entries = entries.borrow();
+            match map_position(&entries, index)? {
+                Some(i) => Ok(entries[i].1.clone()),
+                None => Err(missing_key(index)),
+            }
+        }
+        other => Err(RuntimeError::new(
+            ErrorKind::InvalidOperation,
+            format!("Cannot index into {}", other.type_name()),
+        )),
+    }
+}
+
+// Replace `target[index]` with `value`.
+pub fn assign_index(target: &Value, index: &Value, value: Value) -> Result<(), RuntimeError> {
+    match target {
+        Value::List(items) => {
+            let mut items = items.borrow_mut();
+            let i = list_index(index, items.len())?;
+            items[i] = value;
+            Ok(())
+        }
+        // Setting a missing key adds it at the end.
+        Value::Map(entries) => {
+            let mut entries = entries.borrow_mut();
+            match map_position(&entries, index)? {
+                Some(i) => entries[i].1 = value,
+                None => entries.push((index.clone(), value)),
+            }
+            Ok(())
+        }
+        other => Err(RuntimeError::new(
+            ErrorKind::InvalidOperation,
+            format!("Cannot index into {}", other.type_name()),
+        )),
+    }
+}
+
+// Check that `index` is an int inside a list of `len` items.
+pub fn list_index(index: &Value, len: usize) -> Result<usize, RuntimeError> {
+    match index {
+        Value::Int(i) if *i < 0 => Err(RuntimeError::new(
+            ErrorKind::IndexOutOfBounds,
+            format!("Index {} is negative", i),
+        )),
+        Value::Int(i) if *i as usize >= len => Err(RuntimeError::new(
+            ErrorKind::IndexOutOfBounds,
+            format!("Index {} is out of bounds for a list of length {}", i, len),
+        )),
+        Value::Int(i) => Ok(*i as usize),
+        other => Err(RuntimeError::new(
+            ErrorKind::TypeMismatch,
+            format!("List index should be int, found {}", other.type_name()),
+        )),
+    }
+}
+
+// Find `key` in a map's entries. Only ints, strings and bools can be keys.
+pub fn map_position(
+    entries: &[(Value, Value)],
+    key: &Value,
+) -> Result<Option<usize>, RuntimeError> {
+    let same = |k: &Value| match (k, key) {
+        (Value::Int(a), Value::Int(b)) => a == b,
+        (Value::Str(a), Value::Str(b)) => a == b,
+        (Value::Bool(a), Value::Bool(b)) => a == b,
+        _ => false,
+    };
+    match key {
+        Value::Int(_) | Value::Str(_) | Value::Bool(_) => {
+            Ok(entries.iter().position(|(k, _)| same(k)))
+        }
+        other => Err(RuntimeError::new(
+            ErrorKind::TypeMismatch,
+            format!(
+                "Map keys should be int, str or bool, found {}",
+                other.type_name()
+            ),
+        )),
+    }
+}
+
+pub fn missing_key(key: &Value) -> RuntimeError {
+    let key = match key {
+        Value::Str(s) => format!("{:?}", s),
+        other => other.to_string(),
+    };
+    RuntimeError::new(
+        ErrorKind::KeyNotFound,
+        format!("Key {} not found in map", key),
+    )
+}
+
+// Apply a prefix operator.
+pub fn unary_op(op: &str, operand: Value, mode: IntMode) -> Result<Value, RuntimeError> {
+    match (op, operand) {
+        ("!", Value::Bool(b)) => Ok(Value::Bool(!b)),
+        ("-", Value::Int(n)) => match mode {
+            IntMode::Checked => n
+                .checked_neg()
+                .map(Value::Int)
+                .ok_or_else(|| overflow(format!("-({})", n))),
+            IntMode::Wrapping => Ok(Value::Int(n.wrapping_neg())),
+            IntMode::Saturating => Ok(Value::Int(n.saturating_neg())),
+        },
+        ("-", Value::Float(n)) => Ok(Value::Float(-n)),
+        ("-", Value::Vec2(v)) => Ok(Value::Vec2(-v)),
+        (op, v) => Err(RuntimeError::new(
+            ErrorKind::InvalidOperation,
+            format!("Cannot apply '{}' to {}", op, v.type_name()),
+        )),
+    }
+}
+
+fn int_op(op: &str, l: i64, r: i64, mode: IntMode) -> Result<Value, RuntimeError> {
+    // Dividing by zero has no sensible result in any mode.
+    if (op == "/" || op == "%") && r == 0 {
+        return Err(RuntimeError::new(
+            ErrorKind::DivisionByZero,
+            format!("Cannot compute {} {} 0", l, op),
+        ));
+    }
+    let result = match (op, mode) {
+        ("+", IntMode::Checked) => l.checked_add(r),
+        ("+", IntMode::Wrapping) => Some(l.wrapping_add(r)),
+        ("+", IntMode::Saturating) => Some(l.saturating_add(r)),
+        ("-", IntMode::Checked) => l.checked_sub(r),
+        ("-", IntMode::Wrapping) => Some(l.wrapping_sub(r)),
+        ("-", IntMode::Saturating) => Some(l.saturating_sub(r)),
+        ("*", IntMode::Checked) => l.checked_mul(r),
+        ("*", IntMode::Wrapping) => Some(l.wrapping_mul(r)),
+        ("*", IntMode::Saturating) => Some(l.saturating_mul(r)),
+        // Only the smallest int divided by -1 overflows.
+        ("/", IntMode::Checked) => l.checked_div(r),
+        ("/", IntMode::Wrapping) => Some(l.wrapping_div(r)),
+        ("/", IntMode::Saturating) => Some(l.saturating_div(r)),
+        ("%", IntMode::Checked) => l.checked_rem(r),
+        ("%", _) => Some(l.wrapping_rem(r)),
+        _ => {
+            return match compare(op, l.cmp(&r)) {
+                Some(b) => Ok(Value::Bool(b)),
+                None => Err(unsupported(op, "int", "int")),
+            };
+        }
+    };
+    result
+        .map(Value::Int)
+        .ok_or_else(|| overflow(format!("{} {} {}", l, op, r)))
+}
+
+fn overflow(expr: String) -> RuntimeError {
+    RuntimeError::new(
+        ErrorKind::Overflow,
+        format!("{} doesn't fit in an int", expr),
+    )
+}
+
+fn float_op(op: &str, l: f64, r: f64) -> Result<Value, RuntimeError> {
+    let v = match op {
+        "+" => Value::Float(l + r),
+        "-" => Value::Float(l - r),
+        "*" => Value::Float(l * r),
+        "/" => Value::Float(l / r),
+        "%" => Value::Float(l % r),
+        _ if compare(op, Ordering::Equal).is_none() => {
+            return Err(unsupported(op, "float", "float"));
+        }
+        // NaN compares false with everything, including itself.
+        _ => Value::Bool(match l.partial_cmp(&r) {
+            Some(ord) => compare(op, ord) == Some(true),
+            None => op == "!=",
+        }),
+    };
+    Ok(v)
+}
+
+fn as_f64(n: &Value) -> f64 {
+    match n {
+        Value::Int(n) => *n as f64,
+        Value::Float(n) => *n,
+        _ => f64::NAN,
+    }
+}
+
+// Read `.x` or `.y` of a vector.
+pub fn vec2_field(v: DVec2, field: &str) -> Result<Value, RuntimeError> {
+    match field {
+        "x" => Ok(Value::Float(v.x)),
+        "y" => Ok(Value::Float(v.y)),
+        _ => Err(RuntimeError::new(
+            ErrorKind::InvalidOperation,
+            format!("vec2 has no field '{}', only 'x' and 'y'", field),
+        )),
+    }
+}
+
+// Change `.x` or `.y` of a vector, giving the new vector.
+pub fn set_vec2_field(v: DVec2, field: &str, value: &Value) -> Result<DVec2, RuntimeError> {
+    vec2_field(v, field)?;
+    if !matches!(value, Value::Int(_) | Value::Float(_)) {
+        return Err(RuntimeError::new(
+            ErrorKind::TypeMismatch,
+            format!("vec2 fields hold numbers, found {}", value.type_name()),
+        ));
+    }
+    let n = as_f64(value);
+    Ok(if field == "x" {
+        DVec2::new(n, v.y)
+    } else {
+        DVec2::new(v.x, n)
+    })
+}
+
+// Evaluate a comparison operator from an ordering, None if `op` isn't one.
+fn compare(op: &str, ord: Ordering) -> Option<bool> {
+    match op {
+        "==" => Some(ord == Ordering::Equal),
+        "!=" => Some(ord != Ordering::Equal),
+        "<" => Some(ord == Ordering::Less),
+        "<=" => Some(ord != Ordering::Greater),
+        ">" => Some(ord == Ordering::Greater),
+        ">=" => Some(ord != Ordering::Less),
+        _ => None,
+    }
+}
+
+fn unsupported(op: &str, left: &str, right: &str) -> RuntimeError {
+    RuntimeError::new(
+        ErrorKind::InvalidOperation,
+        format!("Cannot apply '{}' to {} and {}", op, left, right),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn int(op: &str, l: i64, r: i64, mode: IntMode) -> Option<i64> {
+        match int_op(op, l, r, mode) {
+            Ok(Value::Int(n)) => Some(n),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn checked_overflow_is_an_error() {
+        for (op, l, r) in [
+            ("+", i64::MAX, 1),
+            ("-", i64::MIN, 1),
+            ("*", i64::MAX, 2),
+            ("/", i64::MIN, -1),
+            ("%", i64::MIN, -1),
+        ] {
+            let err = int_op(op, l, r, IntMode::Checked).err().unwrap();
+            assert_eq!(err.kind, ErrorKind::Overflow, "{} {} {}", l, op, r);
+        }
+        assert_eq!(int("+", 2, 3, IntMode::Checked), Some(5));
+    }
+
+    #[test]
+    fn wrapping_overflow_wraps() {
+        assert_eq!(int("+", i64::MAX, 1, IntMode::Wrapping), Some(i64::MIN));
+        assert_eq!(int("-", i64::MIN, 1, IntMode::Wrapping), Some(i64::MAX));
+        assert_eq!(int("/", i64::MIN, -1, IntMode::Wrapping), Some(i64::MIN));
+        assert_eq!(int("%", i64::MIN, -1, IntMode::Wrapping), Some(0));
+    }
+
+    #[test]
+    fn saturating_overflow_sticks_at_the_limits() {
+        assert_eq!(int("+", i64::MAX, 1, IntMode::Saturating), Some(i64::MAX));
+        assert_eq!(int("*", i64::MIN, 2, IntMode::Saturating), Some(i64::MIN));
+        assert_eq!(int("/", i64::MIN, -1, IntMode::Saturating), Some(i64::MAX));
+    }
+
+    #[test]
+    fn division_by_zero_fails_in_every_mode() {
+        for mode in [IntMode::Checked, IntMode::Wrapping, IntMode::Saturating] {
+            for op in ["/", "%"] {
+                let err = int_op(op, 1, 0, mode).err().unwrap();
+                assert_eq!(err.kind, ErrorKind::DivisionByZero);
+            }
+        }
+    }
+}
@@ -1,155 +1,665 @@
+use std::rc::Rc;
+
 use crate::int::lexeme::*;
+use crate::int::pragma::StatementEnd;
 
 peg::parser! {
-  pub grammar interpreter_parser() for str {
+  pub grammar interpreter_parser(statement_end: StatementEnd) for str {
       // Skip whitespace.
-      rule _() = ([' ' | '\t' | '\n' | '\r'] / comment())* { () }
+      rule _() = quiet!{([' ' | '\t' | '\n' | '\r'] / comment())*}
+
+      // Skip whitespace without leaving the line.
+      rule inline() = quiet!{([' ' | '\t' | '\r'] / ("/*" (!"*/" !"\n" [_])* "*/"))*}
+
+      rule newline_mode() = {? if statement_end == StatementEnd::Newline { Ok(()) } else { Err("") } }
+
+      // Whitespace inside a statement, which can't cross a line end when newlines end statements.
+      rule sp() = newline_mode() inline() / !newline_mode() _
+
+      // End a simple statement: ";", or the end of the line under "#!mode newlines".
+      // The last statement before a "}" needs neither.
+      // With newlines, a line starting with an operator still continues the line before.
+      rule end()
+          = _ ";"
+          // So does the next arm of a match, after an optional ',': `Idle => print("idle"),`.
+          / quiet!{ &(_ "}") }
+          / quiet!{ &(_ ("," _)? ("}" / pattern() _ "=>")) }
+          / newline_mode() inline() (
+              quiet!{ ("//" (!"\n" [_])*)? "\n" / ![_] }
+              / expected!("end of line")
+          )
 
       // Single-line comment: matches '//' then any characters until a newline. and '/*comment*/' for multi-line comment
-      rule comment() -> () = ("//" (!"\n" [_])* (("\n") / ![_])) { () } / ("/*" (!"*/" [_])* "*/") { () }
+      // "#!" pragma lines are read before parsing (see pragma.rs), so they're skipped like comments.
+      rule comment() = ("//" (!"\n" [_])* (("\n") / ![_])) / ("/*" (!"*/" [_])* "*/") / ("#!" (!"\n" [_])*)
 
             // Parse an identifier.
       rule identifier() -> &'input str
-          = s:$(['a'..='z'|'A'..='Z'] ['a'..='z'|'A'..='Z'|'0'..='9'|'_' | '.']*) { s }
+          = quiet!{ s:$(['a'..='z'|'A'..='Z'] ['a'..='z'|'A'..='Z'|'0'..='9'|'_']*) { s } }
           / expected!("identifier")
 
-      // Parse an integer.
+      // Parse a type annotation.
+      rule type_name() -> &'input str
+          = quiet!{ identifier() } / expected!("type")
+
+      // A character that can continue an identifier, used to stop keywords matching prefixes.
+      rule ident_char() = ['a'..='z'|'A'..='Z'|'0'..='9'|'_']
+
+      // Parse an integer, optionally negative so the most negative int can be written.
       rule int_value() -> i64
-          = n:$(['0'..='9']+) { n.parse().unwrap() }
+          = n:quiet!{ $("-"? ['0'..='9']+) } {? n.parse().or(Err("integer that fits in 64 bits")) }
+          / expected!("integer")
 
-      // parse float
+      // parse float: "1.5", "-0.25", "1e-3", "2.5E6"
       rule float_value()-> f64
-          = n:$((['0'..='9'] + "." ['0'..='9']+)) {n.parse().unwrap()}
+          = quiet!{ n:$("-"? ['0'..='9']+ (("." ['0'..='9']+ exponent()?) / exponent())) {n.parse().unwrap()} }
+          / expected!("float")
 
-      // parse string simple
+      rule exponent() = ['e' | 'E'] ['+' | '-']? ['0'..='9']+
+
+      // parse string simple, used where interpolation makes no sense (patterns)
       rule string_value()-> String
-          = "\"" s:$((!"\"" [_])*) "\"" { s.to_string() }
+          // Only the opening quote is quiet, so an unterminated string reports the missing '"'.
+          = quiet!{"\""} s:(string_char()*) "\"" { s.into_iter().collect() }
+
+      // A character inside a string, a backslash starts an escape sequence.
+      rule string_char() -> char
+          = "\\" c:escape() { c }
+          / !['"' | '\\'] c:[_] { c }
+
+      rule escape() -> char
+          = quiet!{
+              "n" { '\n' } / "t" { '\t' } / "r" { '\r' } / "0" { '\0' }
+              / "\"" { '"' } / "\\" { '\\' } / "{" { '{' } / "}" { '}' }
+          }
+          / expected!("escape sequence")
 
       // Parse a boolean
       rule bool_value() -> bool
-          = "true" { true } / "false" { false }
-
-      rule value() -> Value
-      = v:(
-            n:float_value() { Value::Float(n) }
-          / n:int_value() { Value::Int(n) }
-          / s:string_value() { Value::Str(s.to_string()) }
-          / b:bool_value()  { Value::Bool(b) }
-        ) { v }
+          = quiet!{ "true" !ident_char() { true } / "false" !ident_char() { false } }
+          / expected!("boolean")
 
       // Parse a boolean literal.
       rule bool_literal() -> Expr
-          = "true" { Expr::Literal(Value::Bool(true)) }
-          / "false" { Expr::Literal(Value::Bool(false)) }
+          = s:position!() b:bool_value() e:position!() { Expr::Literal(Value::Bool(b), Span::new(s, e)) }
 
       // Parse a literal expression.
       rule literal_expr() -> Expr
-          = n:int_value() { Expr::Literal(Value::Int(n)) }
+          = s:position!() n:int_value() e:position!() { Expr::Literal(Value::Int(n), Span::new(s, e)) }
+
+      // Parse a float literal expression.
+      rule float_literal() -> Expr
+          = s:position!() n:float_value() e:position!() { Expr::Literal(Value::Float(n), Span::new(s, e)) }
+
+      // Parse a string literal expression. "{<expr>}" inside it is replaced by the value,
+      // "\{" gives a literal brace.
+      rule string_literal() -> Expr
+          = s:position!() quiet!{"\""} parts:(string_part()*) "\"" e:position!() {
+              let span = Span::new(s, e);
+              match parts.as_slice() {
+                  [] => Expr::Literal(Value::Str(String::new()), span),
+                  [Expr::Literal(v @ Value::Str(_), _)] => Expr::Literal(v.clone(), span),
+                  _ => Expr::Interpolated(parts, span),
+              }
+          }
+
+      rule string_part() -> Expr
+          = "{" _ x:expr() _ "}" { x }
+          / s:position!() cs:(!"{" c:string_char() { c })+ e:position!() {
+              Expr::Literal(Value::Str(cs.into_iter().collect()), Span::new(s, e))
+          }
 
       // Parse a variable expression.
       rule variable_expr() -> Expr
-          = id:identifier() { Expr::Variable(id.to_string()) }
-
-      // Parse a factor: bool literal, literal, variable, or parenthesized expression.
-      rule factor() -> Expr
-          = bool_literal()
-          / literal_expr()
-          / variable_expr()
-          / "(" _ e:expr() _ ")" { e }
-
-      // Parse addition expressions.
-      rule add_expr() -> Expr
-          = left:factor() _ "+" _ right:add_expr() { Expr::BinaryOp(Box::new(left), "+".to_string(), Box::new(right)) }
-          / factor()
-
-      // Parse relational expressions with '<'.
-      rule rel_expr() -> Expr
-          = left:add_expr() _ "<" _ right:rel_expr() { Expr::BinaryOp(Box::new(left), "<".to_string(), Box::new(right)) }
-          / add_expr()
-
-      // A relational expression rule stuff.
-      rule expr() -> Expr
-        = s:string_value() {Expr::Literal(Value::Str(s.to_string()))}
-        / n:int_value() {Expr::Literal(Value::Int(n))}
-        / variable_expr()
-        / rel_expr()
-
-      // Parse a variable declaration: "var <id>:<type> = <int>;"
+          = s:position!() id:identifier() e:position!() { Expr::Variable(id.to_string(), Slot::Global, Span::new(s, e)) }
+
+      // Parse an anonymous function: "fn(<params>) { <statements> }"
+      rule lambda_expr() -> Expr
+          = s:position!() "fn" e:position!() _ f:function_rest() {
+              let (parameters, return_type, body) = f;
+              Expr::Lambda(Rc::new(FunctionDecl { name: None, parameters, return_type, body, span: Span::new(s, e) }))
+          }
+
+      // Parse a list literal: "[<expr>, <expr>, ...]", a trailing comma is allowed.
+      rule list_literal() -> Expr
+          = s:position!() quiet!{"["} _ items:(expr() ** (_ "," _)) _ ("," _)? "]" e:position!() {
+              Expr::List(items, Span::new(s, e))
+          }
+
+      // Parse a map literal: "{<expr>: <expr>, ...}", a trailing comma is allowed.
+      rule map_literal() -> Expr
+          = s:position!() quiet!{"{"} _ entries:(map_entry() ** (_ "," _)) _ ("," _)? "}" e:position!() {
+              Expr::Map(entries, Span::new(s, e))
+          }
+
+      rule map_entry() -> (Expr, Expr)
+          = k:expr() _ ":" _ v:expr() { (k, v) }
+
+      // Parse an enum value: "<enum>::<variant>" or "<enum>::<variant>(<expr>, ...)"
+      rule variant_expr() -> Expr
+          = s:position!() en:identifier() "::" v:identifier()
+            payload:(_ "(" _ a:(expr() ** (_ "," _)) _ ")" { a })? e:position!() {
+              Expr::Variant(en.to_string(), v.to_string(), payload.unwrap_or_default(), Span::new(s, e))
+          }
+
+      // Parse a struct constructor: "<id> { <field>: <expr>, ... }"
+      // At least one field is needed, so "for x in xs {}" still reads as a loop.
+      rule struct_literal() -> Expr
+          = s:position!() name:quiet!{ n:identifier() _ "{" { n } } _
+            fields:(field_init() ++ (_ "," _)) _ ("," _)? "}" e:position!() {
+              Expr::Construct(name.to_string(), fields, Span::new(s, e))
+          }
+
+      rule field_init() -> (String, Expr)
+          = id:identifier() _ ":" _ v:expr() { (id.to_string(), v) }
+
+      // Parse an atom: literal, lambda, variable, or parenthesized expression.
+      rule atom() -> Expr
+          = quiet!{ float_literal() / literal_expr() / bool_literal() }
+          / list_literal()
+          / map_literal()
+          / lambda_expr()
+          / variant_expr()
+          / struct_literal()
+          / quiet!{ variable_expr() }
+          / string_literal()
+          / quiet!{"("} _ e:expr() _ ")" { e }
+          / expected!("expression")
+
+      // Operators from lowest to highest precedence, all binary ones are left-associative.
+      rule expr() -> Expr = precedence!{
+          l:(@) _ "??" _ r:@ { binary(l, "??", r) }
+          --
+          l:(@) _ "||" _ r:@ { binary(l, "||", r) }
+          --
+          l:(@) _ "&&" _ r:@ { binary(l, "&&", r) }
+          --
+          l:(@) _ "==" _ r:@ { binary(l, "==", r) }
+          l:(@) _ "!=" _ r:@ { binary(l, "!=", r) }
+          --
+          l:(@) _ "<=" _ r:@ { binary(l, "<=", r) }
+          l:(@) _ ">=" _ r:@ { binary(l, ">=", r) }
+          l:(@) _ "<" _ r:@ { binary(l, "<", r) }
+          l:(@) _ ">" _ r:@ { binary(l, ">", r) }
+          --
+          // Not the start of "x++" or "x--", which would otherwise swallow the next line.
+          l:(@) _ "+" !"+" _ r:@ { binary(l, "+", r) }
+          l:(@) _ "-" !"-" _ r:@ { binary(l, "-", r) }
+          --
+          l:(@) _ "*" _ r:@ { binary(l, "*", r) }
+          l:(@) _ "/" _ r:@ { binary(l, "/", r) }
+          l:(@) _ "%" _ r:@ { binary(l, "%", r) }
+          --
+          s:position!() quiet!{"!"} _ x:@ { unary(s, "!", x) }
+          s:position!() quiet!{"-"} _ x:@ { unary(s, "-", x) }
+          --
+          // Call whatever the callee evaluates to: "<expr>(<args>)"
+          // Arguments are a comma-separated list of expressions.
+          f:(@) _ quiet!{"("} _ args:(expr() ** (_ "," _)) _ ")" e:position!() {
+              let span = Span::new(f.span().start, e);
+              Expr::Call(Box::new(f), args, span)
+          }
+          // Read a struct field: "<expr>.<id>"
+          t:(@) "." f:identifier() e:position!() {
+              let span = Span::new(t.span().start, e);
+              Expr::Field(Box::new(t), f.to_string(), span)
+          }
+          // Index into a list or map: "<expr>[<expr>]"
+          t:(@) _ quiet!{"["} _ i:expr() _ "]" e:position!() {
+              let span = Span::new(t.span().start, e);
+              Expr::Index(Box::new(t), Box::new(i), span)
+          }
+          --
+          a:atom() { a }
+      }
+
+      // Parse a variable declaration: "var <id>:<type> = <expr>;" or "var <id>:<type>;",
+      // or the same with "const" or "global" in place of "var"
       rule var_decl() -> Statement
-          = "var" _ id:identifier() _ ":" _ typ:identifier() _ "=" _ val:value() _ ";" {
+          = s:position!() kind:var_kind() _ id:identifier() _ ":" _ typ:type_name() e:position!()
+            init:(_ "=" _ v:expr() { v })? end() {
               Statement::VarDecl({
                   Variable {
+                      kind,
                       name: id.to_string(),
-                      _type_annotation: typ.to_string(),
-                      value: val,
+                      type_annotation: typ.to_string(),
+                      initializer: init,
+                      span: Span::new(s, e),
                   }
               })
           }
 
-      // Parse an assignment: "<id> = <expr>;"
-      rule assignment() -> Statement
-          = id:identifier() _ "=" _ e:expr() _ ";" {
-              Statement::Assignment { name: id.to_string(), expr: e }
+      // "var", "const" or "global"
+      rule var_kind() -> VarKind
+          = "var" !ident_char() { VarKind::Var }
+          / "const" !ident_char() { VarKind::Const }
+          / "global" !ident_char() { VarKind::Global }
+
+      // Parse a statement that starts with an expression: a call "<expr>(<args>);",
+      // "<target> = <expr>;", "<target> += <expr>;" (also -= *= /= %=), "<target>++;",
+      // "<target>--;" or "<target>, <target> = <expr>, <expr>;".
+      // The leading expression is parsed once and the token after it picks the statement.
+      rule expr_statement() -> Statement
+          = t:expr() tail:statement_tail() {? expr_statement(t, tail) }
+
+      rule statement_tail() -> StatementTail
+          = _ "=" _ v:expr() end() { StatementTail::Assign(v) }
+          / _ op:compound_op() _ v:expr() end() { StatementTail::Compound(op.to_string(), v) }
+          / _ s:position!() op:$("++" / "--") e:position!() end() {
+              StatementTail::Step(op[..1].to_string(), Span::new(s, e))
+          }
+          / _ "," _ ts:(expr() ++ (_ "," _)) _ "=" _ vs:(expr() ** (_ "," _)) end() {
+              StatementTail::Multi(ts, vs)
           }
+          / end() { StatementTail::End }
+
+      rule compound_op() -> &'input str
+          = op:$(['+'|'-'|'*'|'/'|'%']) "=" { op }
 
       // Parse a print statement: "print(<id>);"
       rule print_stmt() -> Statement
-          = "print" _ "(" _ e:expr() _ ")" _ ";" {
+          = "print" _ "(" _ e:expr() _ ")" end() {
               Statement::PrintExpr(e)
           }
 
-      // Parse a for loop: "for <id> in <int>..<int> { <statements> }"
+      // Parse an optional loop label: "<id>:" in front of for/while.
+      rule loop_label() -> String
+          = l:identifier() _ ":" _ { l.to_string() }
+
+      // Parse a for loop: "for <id> in <expr>..<expr> step <expr> { <statements> }"
+      // or "for <id> in <expr> { <statements> }" over a list, map or string.
       rule for_loop() -> Statement
-          = "for" _ id:identifier() _ "in" _ start:int_value() _ ".." _ end:int_value() _ "{" _
+          = label:loop_label()? "for" _ id:identifier() _ "in" _ iterable:iterable() _ "{" _
             stmts:(statement() ** _) _ "}" {
-              Statement::ForLoop { var_name: id.to_string(), start, end, body: stmts }
+              Statement::ForLoop { label, var_name: id.to_string(), iterable, body: stmts }
+          }
+
+      // Parse what a for loop walks over. ".." excludes the end, "..=" includes it.
+      rule iterable() -> Iterable
+          = start:expr() _ ".." inclusive:("="?) _ end:expr()
+            step:(_ "step" !ident_char() _ s:expr() { s })? {
+              Iterable::Range { start, end, inclusive: inclusive.is_some(), step }
           }
+          / c:expr() { Iterable::Collection(c) }
 
       // Parse a while loop: "while (<expr>) { <statements> }"
       rule while_loop() -> Statement
-          = "while" _ "(" _ cond:expr() _ ")" _ "{" _
+          = label:loop_label()? "while" _ "(" _ cond:expr() _ ")" _ "{" _
             stmts:(statement() ** _) _ "}" {
-              Statement::While { condition: cond, body: stmts }
+              Statement::While { label, condition: cond, body: stmts }
+          }
+
+      // Parse "break;" / "continue;", optionally naming the loop: "break <label>;"
+      rule break_stmt() -> Statement
+          = s:position!() "break" !ident_char() label:(sp() l:identifier() { l.to_string() })? e:position!() end() {
+              Statement::Break(label, Span::new(s, e))
+          }
+
+      rule continue_stmt() -> Statement
+          = s:position!() "continue" !ident_char() label:(sp() l:identifier() { l.to_string() })? e:position!() end() {
+              Statement::Continue(label, Span::new(s, e))
+          }
+
+      // Parse a conditional: "if (<expr>) { <statements> } else if (<expr>) { ... } else { ... }"
+      // An "else if" chain is stored as an else branch holding a single nested if.
+      rule if_stmt() -> Statement
+          = "if" _ "(" _ cond:expr() _ ")" _ "{" _
+            stmts:(statement() ** _) _ "}" else_body:(_ "else" _ e:else_branch() { e })? {
+              Statement::If { condition: cond, then_body: stmts, else_body }
           }
 
-      // Parse a return statement: "return <expr>;"
+      // Parse "try { ... } catch (<id>) { ... }"
+      rule try_stmt() -> Statement
+          = "try" _ "{" _ body:(statement() ** _) _ "}" _
+            "catch" _ "(" _ id:identifier() _ ")" _ "{" _ catch_body:(statement() ** _) _ "}" {
+              Statement::Try { body, error_name: id.to_string(), catch_body }
+          }
+
+      // Parse "throw <expr>;"
+      rule throw_stmt() -> Statement
+          = s:position!() "throw" !ident_char() _ x:expr() e:position!() end() {
+              Statement::Throw(x, Span::new(s, e))
+          }
+
+      rule else_branch() -> Vec<Statement>
+          = s:if_stmt() { vec![s] }
+          / "{" _ stmts:(statement() ** _) _ "}" { stmts }
+
+      // Parse a return statement: "return <expr>;" or "return;", which returns nil
       rule return_stmt() -> Statement
-          = "return" _ e:expr() _ ";" { Statement::Return(e) }
+          = "return" !ident_char() sp() e:expr() end() { Statement::Return(e) }
+          / s:position!() "return" !ident_char() e:position!() end() {
+              Statement::Return(Expr::Literal(Value::Unit, Span::new(s, e)))
+          }
 
-      // Parse a function declaration:
-      // "fn <id>(<params>) { <statements> }"
-      // Parameters are a comma-separated list of identifiers.
+      // Parse a function parameter: "<id>" or "<id>: <type>"
+      rule parameter() -> Parameter
+          = id:identifier() typ:(_ ":" _ t:type_name() { t.to_string() })? {
+              Parameter { name: id.to_string(), type_annotation: typ }
+          }
+
+      // Parse what follows "fn" or "fn <id>": "(<params>) -> <type> { <statements> }"
+      // Parameters are a comma-separated list, the return type is optional.
+      rule function_rest() -> (Vec<Parameter>, Option<String>, Vec<Statement>)
+          = "(" _ params:(parameter() ** (_ "," _)) _ ")" _
+            ret:("->" _ t:type_name() _ { t.to_string() })? "{" _
+            stmts:(statement() ** _) _ "}" { (params, ret, stmts) }
+
+      // Parse a function declaration: "fn <id>(<params>) -> <type> { <statements> }"
       rule function_decl() -> Statement
-          = "fn" _ name:identifier() _ "(" _ params:(identifier() ** ("," _))? _ ")" _ "{" _
-            stmts:(statement() ** _) _ "}" {
-                Statement::Function {
-                    name: name.to_string(),
-                    parameters: params.map(|v| v.into_iter().map(|s| s.to_string()).collect())
-                                       .unwrap_or_else(Vec::new),
-                    body: stmts
-                }
-            }
+          = "fn" _ s:position!() name:identifier() e:position!() _ f:function_rest() {
+              let (parameters, return_type, body) = f;
+              Statement::Function(Rc::new(FunctionDecl {
+                  name: Some(name.to_string()),
+                  parameters,
+                  return_type,
+                  body,
+                  span: Span::new(s, e),
+              }))
+          }
+
+      // Parse a struct declaration: "struct <id> { <field>: <type>, ... }"
+      rule struct_decl() -> Statement
+          = "struct" !ident_char() _ s:position!() name:identifier() e:position!() _ "{" _
+            fields:(struct_field() ++ (_ "," _)) _ ("," _)? "}" {
+              Statement::Struct(Rc::new(StructDecl { name: name.to_string(), fields, span: Span::new(s, e) }))
+          }
+
+      rule struct_field() -> Field
+          = id:identifier() _ ":" _ t:type_name() {
+              Field { name: id.to_string(), type_annotation: t.to_string() }
+          }
 
-      // Parse a function call: "<id>(<args>);"
-      // Arguments are a comma-separated list of expressions.
-      rule function_call() -> Statement
-          = name:identifier() _ "(" _ args:(expr() ** ("," _))? _ ")" _ ";" {
-              Statement::FunctionCall { name: name.to_string(), arguments: args.unwrap_or(vec![]) }
+      // Parse an enum declaration: "enum <id> { <variant>, <variant>(<type>, ...), ... }"
+      rule enum_decl() -> Statement
+          = "enum" !ident_char() _ s:position!() name:identifier() e:position!() _ "{" _
+            variants:(enum_variant() ++ (_ "," _)) _ ("," _)? "}" {
+              Statement::Enum(Rc::new(EnumDecl { name: name.to_string(), variants, span: Span::new(s, e) }))
           }
 
+      rule enum_variant() -> Variant
+          = id:identifier() payload:(_ "(" _ t:(type_name() ** (_ "," _)) _ ")" { t })? {
+              Variant {
+                  name: id.to_string(),
+                  payload: payload.unwrap_or_default().into_iter().map(|t| t.to_string()).collect(),
+              }
+          }
+
+      // Parse a match statement: "match <expr> { <pattern> => <statement or block>, ... }"
+      // Arms are tried in order, commas between them are optional.
+      rule match_stmt() -> Statement
+          = "match" !ident_char() _ subject:expr() _ "{" _
+            arms:(match_arm() ** (_ ("," _)?)) _ ("," _)? "}" {
+              Statement::Match { subject, arms }
+          }
+
+      rule match_arm() -> MatchArm
+          = s:position!() pattern:pattern() e:position!() _ "=>" _ body:arm_body() {
+              MatchArm { pattern, body, span: Span::new(s, e) }
+          }
+
+      rule arm_body() -> Vec<Statement>
+          = "{" _ stmts:(statement() ** _) _ "}" { stmts }
+          / s:statement() { vec![s] }
+
+      // Parse a pattern. Capitalised names are enum variants, other names bind the value.
+      rule pattern() -> Pattern
+          = "_" !ident_char() { Pattern::Wildcard }
+          / start:int_value() _ "..=" _ end:int_value() { Pattern::Range(start, end, true) }
+          / start:int_value() _ ".." _ end:int_value() { Pattern::Range(start, end, false) }
+          / n:float_value() { Pattern::Literal(Value::Float(n)) }
+          / n:int_value() { Pattern::Literal(Value::Int(n)) }
+          / s:string_value() { Pattern::Literal(Value::Str(s)) }
+          / b:bool_value() { Pattern::Literal(Value::Bool(b)) }
+          / en:identifier() "::" v:identifier() payload:variant_payload()? {
+              Pattern::Variant(Some(en.to_string()), v.to_string(), payload.unwrap_or_default())
+          }
+          / v:identifier() payload:variant_payload()? {?
+              match (v.starts_with(char::is_uppercase), payload) {
+                  (true, payload) => Ok(Pattern::Variant(None, v.to_string(), payload.unwrap_or_default())),
+                  (false, None) => Ok(Pattern::Binding(v.to_string())),
+                  (false, Some(_)) => Err("enum variant"),
+              }
+          }
+
+      rule variant_payload() -> Vec<Pattern>
+          = _ "(" _ p:(pattern() ** (_ "," _)) _ ")" { p }
+
       // A statement can be one of several alternatives.
       rule statement() -> Statement
           = function_decl()
+          / struct_decl()
+          / enum_decl()
+          / match_stmt()
           / return_stmt()
+          / try_stmt()
+          / throw_stmt()
           / var_decl()
-          / assignment()
           / print_stmt()
           / for_loop()
           / while_loop()
-          / function_call()
+          / if_stmt()
+          / break_stmt()
+          / continue_stmt()
+          / expr_statement()
 
       // A program is a series of statements.
       pub rule program() -> Vec<Statement>
           = _ stmts:(statement() ** _) _ { stmts }
   }
 }
+
+// Build a prefix operation spanning the operator and its operand.
+fn unary(start: usize, op: &str, operand: Expr) -> Expr {
+    let span = Span::new(start, operand.span().end);
+    Expr::UnaryOp(op.to_string(), Box::new(operand), span)
+}
+
+// Whether an expression names something that can be assigned to.
+fn is_place(expr: &Expr) -> bool {
+    matches!(expr, Expr::Variable(..) | Expr::Index(..) | Expr::Field(..))
+}
+
+// What follows the leading expression of an expression statement.
+enum StatementTail {
+    Assign(Expr),
+    Compound(String, Expr),      // the binary operator, "+" for "+="
+    Step(String, Span),          // "+" or "-" for "++" or "--", and where it was written
+    Multi(Vec<Expr>, Vec<Expr>), // the targets after the first, and the values
+    End,
+}
+
+// Build the statement for an expression followed by `tail`.
+fn expr_statement(target: Expr, tail: StatementTail) -> Result<Statement, &'static str> {
+    let place = is_place(&target);
+    match tail {
+        StatementTail::Assign(expr) => match target {
+            Expr::Variable(name, slot, span) => Ok(Statement::Assignment {
+                name,
+                slot,
+                expr,
+                span,
+            }),
+            Expr::Index(target, index, span) => Ok(Statement::IndexAssignment {
+                target: *target,
+                index: *index,
+                expr,
+                span,
+            }),
+            Expr::Field(target, field, span) => Ok(Statement::FieldAssignment {
+                target: *target,
+                field,
+                expr,
+                span,
+            }),
+            _ => Err("assignment target"),
+        },
+        StatementTail::Compound(op, expr) if place => {
+            let span = target.span();
+            Ok(Statement::CompoundAssignment {
+                target,
+                op,
+                expr,
+                span,
+            })
+        }
+        StatementTail::Step(op, op_span) if place => {
+            let span = target.span();
+            Ok(Statement::CompoundAssignment {
+                target,
+                op,
+                expr: Expr::Literal(Value::Int(1), op_span),
+                span,
+            })
+        }
+        StatementTail::Multi(mut targets, values) => {
+            targets.insert(0, target);
+            if !targets.iter().all(is_place) {
+                return Err("assignment targets");
+            }
+            if targets.len() != values.len() {
+                return Err("as many values as targets");
+            }
+            Ok(Statement::MultiAssignment { targets, values })
+        }
+        StatementTail::Compound(..) | StatementTail::Step(..) => Err("assignment target"),
+        StatementTail::End => match target {
+            Expr::Call(..) => Ok(Statement::Expression(target)),
+            _ => Err("function call"),
+        },
+    }
+}
+
+// Build a binary operation spanning both operands.
+fn binary(left: Expr, op: &str, right: Expr) -> Expr {
+    let span = Span::new(left.span().start, right.span().end);
+    Expr::BinaryOp(Box::new(left), op.to_string(), Box::new(right), span)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(source: &str, mode: StatementEnd) -> Vec<Statement> {
+        interpreter_parser::program(source, mode).unwrap()
+    }
+
+    #[test]
+    fn newline_mode_ends_statements_at_line_breaks() {
+        let stmts = parse(
+            "var x: int = 1\nprint(x)\nx = 2; print(x)\n",
+            StatementEnd::Newline,
+        );
+        assert_eq!(stmts.len(), 4);
+        assert!(interpreter_parser::program("print(1) print(2)", StatementEnd::Newline).is_err());
+    }
+
+    #[test]
+    fn newline_mode_continues_lines_starting_with_an_operator() {
+        let stmts = parse("var x: int = 1\n    + 2\nprint(x)", StatementEnd::Newline);
+        assert_eq!(stmts.len(), 2);
+    }
+
+    #[test]
+    fn newline_mode_steps_dont_swallow_the_next_line() {
+        let stmts = parse(
+            "lives--\nprint(lives)\nlives++\nlives--\nlives = 5",
+            StatementEnd::Newline,
+        );
+        assert_eq!(stmts.len(), 5);
+        assert!(matches!(&stmts[0], Statement::CompoundAssignment { op, .. } if op == "-"));
+        assert!(matches!(stmts[1], Statement::PrintExpr(_)));
+        assert!(matches!(&stmts[2], Statement::CompoundAssignment { op, .. } if op == "+"));
+        assert!(matches!(stmts[4], Statement::Assignment { .. }));
+    }
+
+    #[test]
+    fn semicolon_mode_lets_statements_span_lines() {
+        let stmts = parse("var x: int = 1\n+ 2;\nprint(x\n);", StatementEnd::Semicolon);
+        assert_eq!(stmts.len(), 2);
+        assert!(
+            interpreter_parser::program("print(1)\nprint(2);", StatementEnd::Semicolon).is_err()
+        );
+    }
+
+    #[test]
+    fn string_escapes_are_decoded() {
+        let stmts = parse(r#"print("say \"hi\"\t\{x\}\\");"#, StatementEnd::Semicolon);
+        assert!(matches!(
+            &stmts[0],
+            Statement::PrintExpr(Expr::Literal(Value::Str(s), _)) if s == "say \"hi\"\t{x}\\"
+        ));
+        assert!(interpreter_parser::program(r#"print("\q");"#, StatementEnd::Semicolon).is_err());
+    }
+
+    #[test]
+    fn braces_interpolate_expressions() {
+        let stmts = parse(r#"print("score: {score + 1}!");"#, StatementEnd::Semicolon);
+        let Statement::PrintExpr(Expr::Interpolated(parts, _)) = &stmts[0] else {
+            panic!("expected an interpolated string");
+        };
+        assert_eq!(parts.len(), 3);
+        assert!(matches!(parts[1], Expr::BinaryOp(..)));
+    }
+
+    #[test]
+    fn steps_and_compound_ops_become_compound_assignments() {
+        let stmts = parse("x++; xs[0] -= 2; p.y *= 3;", StatementEnd::Semicolon);
+        let ops: Vec<&str> = stmts
+            .iter()
+            .map(|s| match s {
+                Statement::CompoundAssignment { op, .. } => op.as_str(),
+                _ => panic!("expected a compound assignment"),
+            })
+            .collect();
+        assert_eq!(ops, ["+", "-", "*"]);
+    }
+
+    #[test]
+    fn assignments_need_place_targets() {
+        for source in [
+            "f() = 1;",
+            "f() += 1;",
+            "1++;",
+            "a, f() = 1, 2;",
+            "a, b = 1;",
+            "a + b;",
+        ] {
+            assert!(
+                interpreter_parser::program(source, StatementEnd::Semicolon).is_err(),
+                "{}",
+                source
+            );
+        }
+        let stmts = parse("a, b[0], c.d = c.d, a, b[0];", StatementEnd::Semicolon);
+        assert!(
+            matches!(&stmts[0], Statement::MultiAssignment { targets, .. } if targets.len() == 3)
+        );
+    }
+
+    #[test]
+    fn deeply_parenthesized_statements_parse_quickly() {
+        let source = format!("x = {}1{};", "(".repeat(40), ")".repeat(40));
+        let start = std::time::Instant::now();
+        assert_eq!(parse(&source, StatementEnd::Semicolon).len(), 1);
+        assert!(start.elapsed().as_secs() < 2);
+    }
+
+    #[test]
+    fn match_arms_need_no_commas() {
+        for mode in [StatementEnd::Semicolon, StatementEnd::Newline] {
+            for source in [
+                r#"match n { 1 => print("one") 2 => print("two") _ => print("other") }"#,
+                r#"match n { 1 => print("one"), 2 => { print("two"); } _ => print("other"), }"#,
+                "match s {\n    Idle => print(1),\n    Chasing(t) => print(t)\n}",
+            ] {
+                let stmts = parse(source, mode);
+                assert!(
+                    matches!(&stmts[0], Statement::Match { arms, .. } if arms.len() >= 2),
+                    "{}",
+                    source
+                );
+            }
+        }
+    }
+}
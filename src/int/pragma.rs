@@ -0,0 +1,98 @@
+use crate::int::diagnostic::source_excerpt;
+use crate::int::operators::IntMode;
+
+// Script-wide settings, written as "#!<name> <value>" lines anywhere in the script.
+#[derive(Clone, Debug, Default)]
+pub struct Pragmas {
+    // "#!overflow checked|wrapping|saturating"
+    pub overflow: IntMode,
+    // "#!mode semicolons|newlines"
+    pub mode: StatementEnd,
+    // "#!max_depth <calls>", how deeply user functions may recurse.
+    pub max_depth: Option<usize>,
+}
+
+// The interpreter's stack grows with the depth allowed, so keep it within reason.
+pub const MAX_DEPTH_LIMIT: usize = 4096;
+
+// What ends a simple statement like `x = 1`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum StatementEnd {
+    #[default]
+    Semicolon,
+    // The end of the line, or a ';' to put several statements on one line.
+    Newline,
+}
+
+// Collect the pragma lines of a script, rejecting unknown names and values.
+pub fn read_pragmas(source: &str) -> Result<Pragmas, String> {
+    let mut pragmas = Pragmas::default();
+    for (i, text) in source.lines().enumerate() {
+        let Some(pragma) = text.trim_start().strip_prefix("#!") else {
+            continue;
+        };
+        let mut words = pragma.split_whitespace();
+        let name = words.next().unwrap_or("");
+        let value = words.next().unwrap_or("");
+        match (name, value) {
+            ("overflow", "checked") => pragmas.overflow = IntMode::Checked,
+            ("overflow", "wrapping") => pragmas.overflow = IntMode::Wrapping,
+            ("overflow", "saturating") => pragmas.overflow = IntMode::Saturating,
+            ("mode", "semicolons") => pragmas.mode = StatementEnd::Semicolon,
+            ("mode", "newlines") => pragmas.mode = StatementEnd::Newline,
+            ("max_depth", _) => match value.parse::<usize>() {
+                Ok(depth) if (1..=MAX_DEPTH_LIMIT).contains(&depth) => {
+                    pragmas.max_depth = Some(depth)
+                }
+                _ => {
+                    return Err(pragma_error(
+                        source,
+                        i + 1,
+                        format!(
+                            "Invalid max_depth '{}', expected a number from 1 to {}",
+                            value, MAX_DEPTH_LIMIT
+                        ),
+                    ));
+                }
+            },
+            ("mode", _) => {
+                return Err(pragma_error(
+                    source,
+                    i + 1,
+                    format!("Unknown mode '{}', expected semicolons or newlines", value),
+                ));
+            }
+            ("overflow", _) => {
+                return Err(pragma_error(
+                    source,
+                    i + 1,
+                    format!(
+                        "Unknown overflow mode '{}', expected checked, wrapping or saturating",
+                        value
+                    ),
+                ));
+            }
+            _ => {
+                return Err(pragma_error(
+                    source,
+                    i + 1,
+                    format!("Unknown pragma '{}'", name),
+                ));
+            }
+        }
+    }
+    Ok(pragmas)
+}
+
+fn pragma_error(source: &str, line: usize, message: String) -> String {
+    let text = source.lines().nth(line - 1).unwrap_or("");
+    let column = text.len() - text.trim_start().len() + 1;
+    let width = text.trim().chars().count();
+    format!(
+        "pragma error at {}:{}: {}\n{}",
+        line,
+        column,
+        message,
+        source_excerpt(source, line, column, width).trim_end()
+    )
+}
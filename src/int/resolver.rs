@@ -0,0 +1,225 @@
+use std::rc::Rc;
+
+use crate::int::lexeme::*;
+
+// Give every variable reference the (depth, index) of the binding it means,
+// following the blocks it's written in. The interpreter creates one environment
+// for each scope opened here, in the same order, so the slots line up at runtime.
+pub fn resolve_program(stmts: &mut [Statement]) {
+    let mut resolver = Resolver { scopes: Vec::new() };
+    resolver.resolve_block(stmts);
+}
+
+struct Resolver {
+    // Names declared in each enclosing local scope, innermost last. Empty at top level,
+    // where declarations become globals.
+    scopes: Vec<Vec<String>>,
+}
+
+impl Resolver {
+    fn declare(&mut self, name: &str) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.push(name.to_string());
+        }
+    }
+
+    // Find the latest declaration of `name`, or fall back to globals.
+    fn lookup(&self, name: &str) -> Slot {
+        for (depth, scope) in self.scopes.iter().rev().enumerate() {
+            if let Some(index) = scope.iter().rposition(|n| n == name) {
+                return Slot::Local(depth, index);
+            }
+        }
+        Slot::Global
+    }
+
+    fn resolve_scoped_block(&mut self, stmts: &mut [Statement]) {
+        self.scopes.push(Vec::new());
+        self.resolve_block(stmts);
+        self.scopes.pop();
+    }
+
+    fn resolve_block(&mut self, stmts: &mut [Statement]) {
+        for stmt in stmts {
+            self.resolve_statement(stmt);
+        }
+    }
+
+    fn resolve_statement(&mut self, stmt: &mut Statement) {
+        match stmt {
+            Statement::VarDecl(var) => {
+                // The initializer can't see the variable it initialises.
+                if let Some(init) = &mut var.initializer {
+                    self.resolve_expr(init);
+                }
+                // Globals are found by name, like undeclared ones.
+                if var.kind != VarKind::Global {
+                    self.declare(&var.name);
+                }
+            }
+            Statement::PrintExpr(expr) | Statement::Return(expr) => self.resolve_expr(expr),
+            Statement::ForLoop {
+                var_name,
+                iterable,
+                body,
+                ..
+            } => {
+                match iterable {
+                    Iterable::Range {
+                        start, end, step, ..
+                    } => {
+                        self.resolve_expr(start);
+                        self.resolve_expr(end);
+                        if let Some(step) = step {
+                            self.resolve_expr(step);
+                        }
+                    }
+                    Iterable::Collection(expr) => self.resolve_expr(expr),
+                }
+                self.scopes.push(vec![var_name.clone()]);
+                self.resolve_block(body);
+                self.scopes.pop();
+            }
+            Statement::While {
+                condition, body, ..
+            } => {
+                self.resolve_expr(condition);
+                self.resolve_scoped_block(body);
+            }
+            Statement::If {
+                condition,
+                then_body,
+                else_body,
+            } => {
+                self.resolve_expr(condition);
+                self.resolve_scoped_block(then_body);
+                if let Some(else_body) = else_body {
+                    self.resolve_scoped_block(else_body);
+                }
+            }
+            Statement::Function(decl) => {
+                // Declared before the body is resolved so the function can call itself.
+                if let Some(name) = &decl.name {
+                    self.declare(name);
+                }
+                self.resolve_function(decl);
+            }
+            Statement::Expression(expr) => self.resolve_expr(expr),
+            // Struct and enum names live apart from variables.
+            Statement::Struct(_) | Statement::Enum(_) => {}
+            Statement::Match { subject, arms } => {
+                self.resolve_expr(subject);
+                for arm in arms {
+                    // Each arm runs in its own environment, starting with its bindings.
+                    let mut names = Vec::new();
+                    arm.pattern.bindings(&mut names);
+                    self.scopes.push(names);
+                    self.resolve_block(&mut arm.body);
+                    self.scopes.pop();
+                }
+            }
+            Statement::Try {
+                body,
+                error_name,
+                catch_body,
+            } => {
+                self.resolve_scoped_block(body);
+                self.scopes.push(vec![error_name.clone()]);
+                self.resolve_block(catch_body);
+                self.scopes.pop();
+            }
+            Statement::Throw(expr, _) => self.resolve_expr(expr),
+            Statement::CompoundAssignment { target, expr, .. } => {
+                self.resolve_expr(target);
+                self.resolve_expr(expr);
+            }
+            Statement::MultiAssignment {
+                targets, values, ..
+            } => {
+                for value in values {
+                    self.resolve_expr(value);
+                }
+                for target in targets {
+                    self.resolve_expr(target);
+                }
+            }
+            Statement::Assignment {
+                name, slot, expr, ..
+            } => {
+                self.resolve_expr(expr);
+                *slot = self.lookup(name);
+            }
+            Statement::IndexAssignment {
+                target,
+                index,
+                expr,
+                ..
+            } => {
+                self.resolve_expr(target);
+                self.resolve_expr(index);
+                self.resolve_expr(expr);
+            }
+            Statement::FieldAssignment { target, expr, .. } => {
+                self.resolve_expr(target);
+                self.resolve_expr(expr);
+            }
+            Statement::Break(..) | Statement::Continue(..) => {}
+        }
+    }
+
+    fn resolve_expr(&mut self, expr: &mut Expr) {
+        match expr {
+            Expr::Literal(..) => {}
+            Expr::Variable(name, slot, _) => *slot = self.lookup(name),
+            Expr::BinaryOp(left, _, right, _) => {
+                self.resolve_expr(left);
+                self.resolve_expr(right);
+            }
+            Expr::UnaryOp(_, operand, _) => self.resolve_expr(operand),
+            Expr::Call(callee, arguments, _) => {
+                self.resolve_expr(callee);
+                for arg in arguments {
+                    self.resolve_expr(arg);
+                }
+            }
+            Expr::Lambda(decl) => self.resolve_function(decl),
+            Expr::List(items, _) => {
+                for item in items {
+                    self.resolve_expr(item);
+                }
+            }
+            Expr::Map(entries, _) => {
+                for (key, value) in entries {
+                    self.resolve_expr(key);
+                    self.resolve_expr(value);
+                }
+            }
+            Expr::Construct(_, fields, _) => {
+                for (_, value) in fields {
+                    self.resolve_expr(value);
+                }
+            }
+            Expr::Field(target, _, _) => self.resolve_expr(target),
+            Expr::Variant(_, _, payload, _) | Expr::Interpolated(payload, _) => {
+                for value in payload {
+                    self.resolve_expr(value);
+                }
+            }
+            Expr::Index(target, index, _) => {
+                self.resolve_expr(target);
+                self.resolve_expr(index);
+            }
+        }
+    }
+
+    fn resolve_function(&mut self, decl: &mut Rc<FunctionDecl>) {
+        // Declarations are only shared once the program runs.
+        let decl = Rc::get_mut(decl).expect("function declaration shared before resolving");
+        // Parameters and body share the call's environment, whose parent
+        // is the scope the function is defined in.
+        self.scopes
+            .push(decl.parameters.iter().map(|p| p.name.clone()).collect());
+        self.resolve_block(&mut decl.body);
+        self.scopes.pop();
+    }
+}
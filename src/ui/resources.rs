@@ -8,6 +8,8 @@ pub struct CodeInput {
 #[derive(Debug, Clone, Event)]
 pub struct PrintEvent {
     pub message: String,
+    // Diagnostics are drawn in red so they stand out from normal output.
+    pub is_error: bool,
 }
 #[derive(Debug, Clone, Event)]
 pub struct SpawnEvent {}
@@ -1,5 +1,7 @@
 use bevy::prelude::*;
 
+pub const ERROR_TEXT_COLOR: TextColor = TextColor(Color::srgb(0.9, 0.2, 0.2));
+
 pub fn sample_ui_style() -> Node {
     Node {
         flex_direction: FlexDirection::Row,
@@ -9,7 +9,7 @@ use super::{
         enemy_components::Enemy,
         enemy_systems::{despawn_enemies, spawn_enemies},
     },
-    style::{sample_ui_style, text_sample_ui_style},
+    style::{ERROR_TEXT_COLOR, sample_ui_style, text_sample_ui_style},
 };
 
 pub fn spawn_camera(mut commands: Commands, windows_query: Query<&Window, With<PrimaryWindow>>) {
@@ -17,7 +17,7 @@ pub fn spawn_camera(mut commands: Commands, windows_query: Query<&Window, With<P
 
     commands.spawn((
         Transform::from_xyz(window.width() / 2.0, window.height() / 2.0, 0.0),
-        Camera2d { ..default() },
+        Camera2d,
     ));
 }
 
@@ -57,7 +57,12 @@ pub fn run_code(
 
 pub fn handle_print_event(mut commands: Commands, mut events: EventReader<PrintEvent>) {
     for ev in events.read() {
-        spawn_text(&mut commands, ev.message.clone());
+        let color = if ev.is_error {
+            ERROR_TEXT_COLOR
+        } else {
+            TextColor::WHITE
+        };
+        spawn_text(&mut commands, ev.message.clone(), color);
     }
 }
 
@@ -72,7 +77,7 @@ pub fn handle_spawn_event(
     }
 }
 
-pub fn spawn_text(commands: &mut Commands, text: impl Into<String>) -> Entity {
+pub fn spawn_text(commands: &mut Commands, text: impl Into<String>, color: TextColor) -> Entity {
     let text = text.into();
     commands
         .spawn((sample_ui_style(), TextUI {}))
@@ -86,7 +91,7 @@ pub fn spawn_text(commands: &mut Commands, text: impl Into<String>) -> Entity {
                             font_size: 32.0,
                             ..Default::default()
                         },
-                        TextColor::WHITE,
+                        color,
                         TextLayout::new_with_justify(JustifyText::Left),
                         Printedtext {},
                     ));